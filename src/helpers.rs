@@ -1,6 +1,8 @@
 
 pub mod file_type;
 pub mod event;
+pub mod crypt;
+pub mod source;
 
 use std::{fmt::Write};
 use file_type::FileType;
@@ -30,14 +32,58 @@ pub fn u32_to_u8_arr(x:u32) -> Vec<u8> {
 
 /// Representing a file entry in the KFN file.
 #[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Entry {
     pub file_type: FileType,
     pub filename: String,
+    /// Length of the plain file.
     pub len1: usize,
     pub offset: usize,
+    /// Length of the file as stored in the KFN, padded to the AES block size if encrypted.
     pub len2: usize,
+    /// Entry flags, see `crypt::FLAG_ENCRYPTED`.
     pub flags: usize,
+    /// The payload, serialized as base64.
+    #[cfg_attr(feature = "serde", serde(with = "base64_payload", default, skip_serializing_if = "Vec::is_empty"))]
     pub file_bin: Vec<u8>,
+    /// If the payload is not loaded into `file_bin` yet, the location it has to be read from in the source.
+    /// Set it to None, when replacing the data of such an entry.
+    /// Serializing an entry that is not loaded fails, as the payload would be missing when exporting it.
+    #[cfg_attr(feature = "serde", serde(skip_deserializing, skip_serializing_if = "Option::is_none", serialize_with = "not_loaded"))]
+    pub deferred: Option<SourceLocation>,
 }
 
+/// Location of a payload in the source of the KFN, as it was in the directory when parsing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SourceLocation {
+    /// Offset from the start of the file.
+    pub offset: usize,
+    /// Length of the plain file.
+    pub len1: usize,
+    /// Length of the file as stored.
+    pub len2: usize,
+    /// Entry flags, see `crypt::FLAG_ENCRYPTED`.
+    pub flags: usize,
+}
+
+/// Fails serializing an entry with a deferred payload, see `Kfn::load_all`.
+#[cfg(feature = "serde")]
+fn not_loaded<S: serde::Serializer>(_: &Option<SourceLocation>, _: S) -> Result<S::Ok, S::Error> {
+    Err(serde::ser::Error::custom("the payload of the entry is not loaded"))
+}
+
+/// Serializing the payload of the entries as base64.
+#[cfg(feature = "serde")]
+mod base64_payload {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error as _};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(data))
+    }
 
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        STANDARD.decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
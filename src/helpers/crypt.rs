@@ -0,0 +1,188 @@
+use aes::Aes128;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit, generic_array::GenericArray};
+
+/// Bit in the directory entry's flags, that marks the payload as encrypted.
+pub const FLAG_ENCRYPTED: usize = 0x01;
+
+/// Size of an AES block. Encrypted payloads are always padded to a multiple of this.
+pub const BLOCK_SIZE: usize = 16;
+
+/// Returns true, if the entry flags mark the payload as encrypted.
+pub fn is_encrypted(flags: usize) -> bool {
+    flags & FLAG_ENCRYPTED != 0
+}
+
+/// Decrypts a stored payload with the FLID key, using AES-128 in ECB mode.
+/// The stored data is `len2` long, the result is cut back to the plain `len1` length.
+/// Returns None, if the key is not a valid 16 byte AES key.
+/// Only used by the tests, to check `DecryptReader` against the whole payload.
+#[cfg(test)]
+pub fn decrypt(key: &[u8], stored: &[u8], plain_len: usize) -> Option<Vec<u8>> {
+
+    let cipher = Aes128::new_from_slice(key).ok()?;
+
+    let mut data: Vec<u8> = Vec::with_capacity(stored.len());
+
+    // only whole blocks can be decrypted, a trailing partial block is not valid data
+    for chunk in stored.chunks_exact(BLOCK_SIZE) {
+
+        let mut block = GenericArray::clone_from_slice(chunk);
+        cipher.decrypt_block(&mut block);
+        data.extend_from_slice(&block);
+    }
+
+    data.truncate(plain_len);
+
+    Some(data)
+}
+
+/// Encrypts a plain payload with the FLID key, using AES-128 in ECB mode.
+/// The data is padded with zeroes to the AES block size, the result is the `len2` long stored data.
+/// Returns None, if the key is not a valid 16 byte AES key.
+/// Only used by the tests, to check `EncryptWriter` against the whole payload.
+#[cfg(test)]
+pub fn encrypt(key: &[u8], plain: &[u8]) -> Option<Vec<u8>> {
+
+    let cipher = Aes128::new_from_slice(key).ok()?;
+
+    let mut data: Vec<u8> = Vec::with_capacity(stored_len(plain.len()));
+
+    for chunk in plain.chunks(BLOCK_SIZE) {
+
+        // the last block gets padded with zeroes
+        let mut block = GenericArray::clone_from_slice(&[0u8; BLOCK_SIZE]);
+        block[..chunk.len()].copy_from_slice(chunk);
+        cipher.encrypt_block(&mut block);
+        data.extend_from_slice(&block);
+    }
+
+    Some(data)
+}
+
+/// Returns the stored length of an encrypted payload, which is the plain length padded to the AES block size.
+pub fn stored_len(plain_len: usize) -> usize {
+    plain_len.div_ceil(BLOCK_SIZE) * BLOCK_SIZE
+}
+
+/// Generates a new random FLID key.
+pub fn generate_key() -> Vec<u8> {
+    rand::random::<[u8; BLOCK_SIZE]>().to_vec()
+}
+
+/// Reader, that decrypts a stored payload block by block, so it never has to be in memory at once.
+pub struct DecryptReader<R: std::io::Read> {
+    inner: R,
+    cipher: Aes128,
+    block: [u8; BLOCK_SIZE],
+    /// Position of the next byte to return from the block.
+    pos: usize,
+    /// Number of valid plain bytes in the block.
+    filled: usize,
+    /// Number of plain bytes not yet read from the block or the inner reader.
+    remaining: usize,
+}
+
+impl<R: std::io::Read> DecryptReader<R> {
+    /// Creates a reader over the stored data, that returns `plain_len` bytes of decrypted data.
+    /// Returns None, if the key is not a valid 16 byte AES key.
+    pub fn new(key: &[u8], inner: R, plain_len: usize) -> Option<Self> {
+        Some(Self {
+            inner,
+            cipher: Aes128::new_from_slice(key).ok()?,
+            block: [0; BLOCK_SIZE],
+            pos: 0,
+            filled: 0,
+            remaining: plain_len,
+        })
+    }
+}
+
+impl<R: std::io::Read> std::io::Read for DecryptReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+
+        if buf.is_empty() || (self.pos == self.filled && self.remaining == 0) {
+            return Ok(0);
+        }
+
+        // decrypt the next block, if the current one is used up
+        if self.pos == self.filled {
+
+            self.inner.read_exact(&mut self.block)?;
+
+            let block = GenericArray::from_mut_slice(&mut self.block);
+            self.cipher.decrypt_block(block);
+
+            self.pos = 0;
+            self.filled = BLOCK_SIZE.min(self.remaining);
+            self.remaining -= self.filled;
+        }
+
+        let n = buf.len().min(self.filled - self.pos);
+        buf[..n].copy_from_slice(&self.block[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+/// Writer, that encrypts the data block by block, padding the last block with zeroes on finishing.
+pub struct EncryptWriter<W: std::io::Write> {
+    inner: W,
+    cipher: Aes128,
+    block: [u8; BLOCK_SIZE],
+    /// Number of bytes waiting in the block.
+    filled: usize,
+}
+
+impl<W: std::io::Write> EncryptWriter<W> {
+    /// Creates a writer, that writes the encrypted data into the inner writer.
+    /// Returns None, if the key is not a valid 16 byte AES key.
+    pub fn new(key: &[u8], inner: W) -> Option<Self> {
+        Some(Self {
+            inner,
+            cipher: Aes128::new_from_slice(key).ok()?,
+            block: [0; BLOCK_SIZE],
+            filled: 0,
+        })
+    }
+
+    /// Pads and writes the last block, then returns the inner writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+
+        if self.filled > 0 {
+            self.block[self.filled..].fill(0);
+            self.write_block()?;
+        }
+
+        Ok(self.inner)
+    }
+
+    /// Encrypts and writes the full block.
+    fn write_block(&mut self) -> std::io::Result<()> {
+
+        let block = GenericArray::from_mut_slice(&mut self.block);
+        self.cipher.encrypt_block(block);
+        self.filled = 0;
+
+        self.inner.write_all(&self.block)
+    }
+}
+
+impl<W: std::io::Write> std::io::Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+
+        let n = buf.len().min(BLOCK_SIZE - self.filled);
+        self.block[self.filled..self.filled + n].copy_from_slice(&buf[..n]);
+        self.filled += n;
+
+        if self.filled == BLOCK_SIZE {
+            self.write_block()?;
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
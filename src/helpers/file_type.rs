@@ -1,5 +1,6 @@
 /// File types, that indicate what kind of files can occur in a KFN file.
 #[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FileType {
     SongIni,
     Music,
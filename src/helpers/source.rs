@@ -0,0 +1,71 @@
+use std::io::{Read, Seek, SeekFrom};
+
+/// Anything a KFN file can be read from, like a file, a buffer in memory or a memory map.
+pub trait KfnSource: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> KfnSource for T {}
+
+/// The source of a KFN file, with the position where the file starts in it.
+/// Every position is relative to the start of the KFN file.
+pub struct Source {
+    reader: Box<dyn KfnSource>,
+    start: u64,
+    len: usize,
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+impl Source {
+    /// Creates a source from the current position of the reader to its end.
+    pub fn new(mut reader: Box<dyn KfnSource>) -> std::io::Result<Self> {
+
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+
+        Ok(Self { reader, start, len: end.saturating_sub(start) as usize })
+    }
+
+    /// Creates a source from a file in memory.
+    pub fn from_bytes(data: Vec<u8>) -> Self {
+        let len = data.len();
+        Self { reader: Box::new(std::io::Cursor::new(data)), start: 0, len }
+    }
+
+    /// Creates an empty source, used for new files.
+    pub fn empty() -> Self {
+        Self::from_bytes(Vec::new())
+    }
+
+    /// The length of the KFN file in the source.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true, if the source has no data.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Moves the reader to the position.
+    pub fn seek(&mut self, position: usize) -> std::io::Result<()> {
+        self.reader.seek(SeekFrom::Start(self.start + position as u64))?;
+        Ok(())
+    }
+
+    /// Returns a reader limited to the given length, starting at the position.
+    pub fn take(&mut self, position: usize, length: usize) -> std::io::Result<std::io::Take<&mut dyn KfnSource>> {
+        self.seek(position)?;
+        Ok(Read::take(self.reader.as_mut() as &mut dyn KfnSource, length as u64))
+    }
+}
+
+impl Read for Source {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
@@ -0,0 +1,263 @@
+use crate::{Kfn, KfnParseError};
+use crate::helpers::Entry;
+use crate::helpers::file_type::FileType;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_header::tag::{Genre, Difficulty};
+use crate::kfn_ini::eff::{Eff, Anim, AnimEntry, Action};
+use crate::kfn_ini::trajectory::Trajectory;
+
+/// The ID of the background layer.
+const BACKGROUND_ID: usize = 51;
+
+/// Builder for authoring a KFN file from scratch.
+/// Times are in centiseconds, the same unit the syncs and animations are stored in.
+#[derive(Debug, Clone, Default)]
+pub struct KfnBuilder {
+    header: KfnHeader,
+    /// The main audio track, with its file name.
+    source: Option<(String, Vec<u8>)>,
+    /// The track with the vocals included, and if it replaces the main track.
+    vocal: Option<(String, Vec<u8>, bool)>,
+    /// Background images, with the time they are shown from.
+    backgrounds: Vec<(usize, String, Vec<u8>)>,
+    fonts: Vec<(String, Vec<u8>)>,
+    layers: Vec<LyricLayer>,
+    /// Files to encrypt with the FLID key.
+    encrypted: Vec<String>,
+}
+
+/// A text layer of the lyrics, with the settings of its Eff section.
+#[derive(Debug, Clone, Default)]
+pub struct LyricLayer {
+    /// The lines of the layer, as fragments with their sync times.
+    /// Fragments can't contain '/', and spaces only at their end, as words are split on spaces.
+    pub lines: Vec<Vec<(usize, String)>>,
+    /// Font file name and size. The file has to be added with `KfnBuilder::font`, if it is not a system font.
+    pub font: Option<(String, u32)>,
+    /// Color of the sung fragments, like #FFFFFFFF.
+    pub active_color: Option<String>,
+    /// Color of the fragments not sung yet.
+    pub inactive_color: Option<String>,
+    pub trajectory: Trajectory,
+}
+
+impl LyricLayer {
+    /// Creates an empty layer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a line of fragments with their sync times.
+    pub fn line(mut self, fragments: Vec<(usize, &str)>) -> Self {
+        self.lines.push(fragments.into_iter().map(|(sync, fragment)| (sync, fragment.to_string())).collect());
+        self
+    }
+
+    /// Sets the font of the layer.
+    pub fn font(mut self, filename: &str, size: u32) -> Self {
+        self.font = Some((filename.to_string(), size));
+        self
+    }
+
+    /// Sets the colors of the sung and the not yet sung fragments.
+    pub fn colors(mut self, active: &str, inactive: &str) -> Self {
+        self.active_color = Some(active.to_string());
+        self.inactive_color = Some(inactive.to_string());
+        self
+    }
+
+    /// Sets the trajectory of the layer.
+    pub fn trajectory(mut self, trajectory: Trajectory) -> Self {
+        self.trajectory = trajectory;
+        self
+    }
+}
+
+impl KfnBuilder {
+    /// Creates a builder for an empty file.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn title(mut self, title: &str) -> Self {
+        self.header.title = title.to_string();
+        self
+    }
+
+    pub fn artist(mut self, artist: &str) -> Self {
+        self.header.artist = artist.to_string();
+        self
+    }
+
+    pub fn album(mut self, album: &str) -> Self {
+        self.header.album = album.to_string();
+        self
+    }
+
+    pub fn composer(mut self, composer: &str) -> Self {
+        self.header.composer = composer.to_string();
+        self
+    }
+
+    pub fn year(mut self, year: &str) -> Self {
+        self.header.year = year.to_string();
+        self
+    }
+
+    pub fn genre(mut self, genre: Genre) -> Self {
+        self.header.genre = genre.into();
+        self
+    }
+
+    /// Sets the language code of the lyrics.
+    pub fn language(mut self, language: &str) -> Self {
+        self.header.language = language.to_string();
+        self
+    }
+
+    /// Sets the difficulty for men and women.
+    pub fn difficulty(mut self, men: Difficulty, women: Difficulty) -> Self {
+        self.header.diff_men = men.value();
+        self.header.diff_women = women.value();
+        self
+    }
+
+    /// Sets the main audio track, played during playback.
+    pub fn source(mut self, filename: &str, data: Vec<u8>) -> Self {
+        self.source = Some((filename.to_string(), data));
+        self
+    }
+
+    /// Sets the track with the vocals included. It has to be an mp3 file.
+    /// If `replaces` is true, it is played instead of the main track, otherwise along with it.
+    pub fn vocal_track(mut self, filename: &str, data: Vec<u8>, replaces: bool) -> Self {
+        self.vocal = Some((filename.to_string(), data, replaces));
+        self
+    }
+
+    /// Adds a background image, shown from the time given.
+    /// The same file can be shown multiple times, its data is only stored once.
+    pub fn background(mut self, time: usize, filename: &str, data: Vec<u8>) -> Self {
+        self.backgrounds.push((time, filename.to_string(), data));
+        self
+    }
+
+    /// Adds a font file, used by the lyric layers.
+    pub fn font(mut self, filename: &str, data: Vec<u8>) -> Self {
+        self.fonts.push((filename.to_string(), data));
+        self
+    }
+
+    /// Adds a text layer of the lyrics.
+    pub fn lyrics(mut self, layer: LyricLayer) -> Self {
+        self.layers.push(layer);
+        self
+    }
+
+    /// Marks a file to be encrypted with the FLID key on export.
+    pub fn encrypt(mut self, filename: &str) -> Self {
+        self.encrypted.push(filename.to_string());
+        self
+    }
+
+    /// Builds the KFN, with the header, the directory, the Materials and the Eff sections in the Song.ini.
+    pub fn build(self) -> Result<Kfn, KfnParseError> {
+
+        let mut kfn = Kfn::new();
+        kfn.header = KfnHeader { flid: kfn.header.flid, ..self.header };
+
+        let (source_name, source_data) = match self.source {
+            Some(source) => source,
+            None => return Err(KfnParseError::MissingIniValue { 
+                section: "General".to_string(), key: "Source".to_string() 
+            }),
+        };
+        add_entry(&mut kfn, &source_name, FileType::Music, source_data);
+        kfn.header.source_file = source_name.clone();
+
+        if let Some((filename, data, replaces)) = self.vocal {
+            add_entry(&mut kfn, &filename, FileType::Music, data);
+            kfn.data.song.set_secondary_source(&filename, replaces);
+        }
+
+        // the background layer is always the first, the player reads the backgrounds from it
+        let mut backgrounds = self.backgrounds;
+        backgrounds.sort_by_key(|(time, _, _)| *time);
+
+        if !backgrounds.is_empty() {
+            let mut anims: Vec<Anim> = Vec::new();
+
+            for (time, filename, data) in backgrounds.iter().cloned() {
+                add_entry(&mut kfn, &filename, FileType::Image, data);
+                anims.push(Anim { 
+                    time, 
+                    anim_entries: vec![AnimEntry { action: Action::ChgBgImg(filename), ..Default::default() }],
+                });
+            }
+
+            let mut eff = Eff::new(BACKGROUND_ID, 1);
+            eff.initial_lib_image = Some(backgrounds[0].1.clone());
+            eff.anims = anims;
+            kfn.data.song.effs.push(eff);
+        }
+
+        for (filename, data) in self.fonts {
+            add_entry(&mut kfn, &filename, FileType::Font, data);
+        }
+
+        for (i, layer) in self.layers.into_iter().enumerate() {
+
+            let num = kfn.data.song.effs.len() + 1;
+            let mut eff = Eff::new(i + 1, num);
+
+            for (line_n, line) in layer.lines.into_iter().enumerate() {
+
+                // the fragments have to split the same way when the Song.ini is read back
+                let invalid = line.iter().any(|(_, fragment)| {
+                    let word = fragment.strip_suffix(' ').unwrap_or(fragment);
+                    word.is_empty() || word.contains(['/', ' '])
+                });
+                eff.add_line(line);
+                if invalid {
+                    return Err(KfnParseError::InvalidIniValue { 
+                        section: format!("Eff{}", num), key: format!("Text{}", line_n), value: eff.texts[line_n].to_ini_value() 
+                    });
+                }
+            }
+
+            eff.initial_font = layer.font;
+            eff.initial_active_color = layer.active_color;
+            eff.initial_inactive_color = layer.inactive_color;
+            eff.initial_trajectory = layer.trajectory;
+            kfn.data.song.effs.push(eff);
+        }
+
+        kfn.update()?;
+
+        // after the update, so the Song.ini can be encrypted as well
+        for filename in &self.encrypted {
+            kfn.set_encrypted(filename, true);
+        }
+
+        Ok(kfn)
+    }
+}
+
+/// Adds a file from memory, if there is no file with the same name yet.
+fn add_entry(kfn: &mut Kfn, filename: &str, file_type: FileType, data: Vec<u8>) {
+
+    if kfn.data.get_entry_by_name(filename).is_some() {
+        return;
+    }
+
+    kfn.data.add_entry(Entry { 
+        file_type,
+        filename: filename.to_string(),
+        len1: data.len(),
+        offset: kfn.data.get_next_offset(),
+        len2: data.len(),
+        flags: 0,
+        file_bin: data,
+        deferred: None,
+    });
+}
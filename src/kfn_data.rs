@@ -1,11 +1,13 @@
-use crate::helpers::{Entry, u32_to_u8_arr};
-use crate::helpers::file_type::{FileType, ToBinary};
+use crate::helpers::{Entry, u32_to_u8_arr, crypt};
+use crate::helpers::file_type::FileType;
 use crate::kfn_ini::KfnIni;
+use crate::KfnParseError;
 
 
 /// The header of a .kfn file, which can be found at the beginning of a said file.
 #[derive(derivative::Derivative, Clone, Default)]
 #[derivative(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KfnData {
     /// The location of the Songs.ini file.
     pub path_song_ini: String,
@@ -52,54 +54,81 @@ impl KfnData {
     }
 
     /// Reads the INI file into the struct.
-    pub fn read_ini(&mut self) {
+    pub fn read_ini(&mut self) -> Result<(), KfnParseError> {
 
-        //self.song.ini = Ini::load_from_str(String::from_utf8(self.get_songs_ini().unwrap().file_bin).unwrap().as_str()).unwrap();
-        self.song.ini = ini::Ini::load_from_str(String::from_utf8(self.get_entry_by_name("Song.ini").unwrap().file_bin).unwrap().as_str()).unwrap();
-        
-    }
+        let entry = match self.get_entry_by_name("Song.ini") {
+            Some(entry) => entry,
+            None => return Err(KfnParseError::EntryNotFound("Song.ini".to_string())),
+        };
+
+        let text = match String::from_utf8(entry.file_bin) {
+            Ok(text) => text,
+            Err(_) => return Err(KfnParseError::Utf8ConversionError),
+        };
 
-    /// Updates the ini file. Removes the Song.ini entry, then recreates the INI file from the struct.
-    pub fn update_ini(&mut self) {
+        self.song.ini = match ini::Ini::load_from_str(&text) {
+            Ok(ini) => ini,
+            Err(e) => return Err(KfnParseError::InvalidIni(e.to_string())),
+        };
 
-        // remove the entry
-        self.remove_entry_by_name("Song.ini");
+        Ok(())
+    }
 
+    /// Updates the ini file. Recreates the INI file from the typed sections, and replaces the Song.ini entry in place,
+    /// so the order of the entries is kept. If there is no Song.ini yet, it is added to the end.
+    pub fn update_ini(&mut self) -> Result<(), KfnParseError> {
 
         // updating the ini
         self.song.set_materials(self.entries.clone());
+        self.song.write_ini();
 
         // creating a destination vector for the data
         let mut writer = Vec::new();
 
         // write the data into the vector
-        self.song.ini.write_to(&mut writer).unwrap();
+        self.song.ini.write_to(&mut writer)?;
         let data = writer.to_owned();
 
-        //  create a new entry
-        let new_entry = Entry {
-            file_type: FileType::SongIni,
-            filename: "Song.ini".to_string(),
-            len1: data.len(),
-            offset: 0,
-            len2: data.len(),
-            flags: usize::default(),
-            file_bin: data,
-        };
-        // and add the entry
-        self.add_entry(new_entry);
+        match self.entries.iter_mut().find(|entry| entry.filename == "Song.ini") {
+            // keep the flags of the previous entry, so an encrypted Song.ini stays encrypted
+            Some(entry) => {
+                entry.len1 = data.len();
+                entry.len2 = data.len();
+                entry.file_bin = data;
+                entry.deferred = None;
+            },
+            None => {
+                //  create a new entry
+                let new_entry = Entry {
+                    file_type: FileType::SongIni,
+                    filename: "Song.ini".to_string(),
+                    len1: data.len(),
+                    offset: self.get_next_offset(),
+                    len2: data.len(),
+                    flags: 0,
+                    file_bin: data,
+                    deferred: None,
+                };
+                // and add the entry
+                self.add_entry(new_entry);
+            },
+        }
         
-
+        Ok(())
     }
 
     /// Used internally before writing to binary, to readjust the offsets that became misaligned.
     fn adjust_dir_offset(&mut self) {
 
+        if self.entries.is_empty() {
+            return;
+        }
+
         self.entries[0].offset = 0;
         
         for i in 1..self.entries.len() {
         
-            self.entries[i].offset = self.entries[i-1].offset + self.entries[i-1].len1;
+            self.entries[i].offset = self.entries[i-1].offset + self.entries[i-1].len2;
             
         }
 
@@ -115,10 +144,10 @@ impl KfnData {
     }
 
     /// Adding a new entry from the data.
-    pub fn add_entry_from_file(&mut self, filename: &str) {
+    pub fn add_entry_from_file(&mut self, filename: &str) -> Result<(), KfnParseError> {
 
         // reading the file from the file system
-        let new_file = std::fs::read(filename).unwrap();
+        let new_file = std::fs::read(filename)?;
         
         // splitting it at the point to get the extension
         let parts : Vec<&str> = filename.split('.').collect();
@@ -141,8 +170,10 @@ impl KfnData {
             None => FileType::INVALID,
         };
 
-        let filename = std::path::Path::new(filename);
-        let filename = filename.file_name().unwrap().to_str().unwrap();
+        let filename = match std::path::Path::new(filename).file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid file name").into()),
+        };
 
         // create an entry
         let new_entry = Entry {
@@ -154,6 +185,7 @@ impl KfnData {
             len2: new_file.len(),
             flags: 0,
             file_bin: new_file,
+            deferred: None,
         };
 
         // add the entry to the library
@@ -162,12 +194,11 @@ impl KfnData {
         // update the ini, so that it contains the new file as well
         // we only do this, if it is not a Song.ini file
         if extension != FileType::SongIni {
-            self.update_ini();
+            self.update_ini()
         } else {
-            self.read_ini();
+            self.read_ini()?;
+            self.song.load()
         }
-        
-
     }
 
     /// Returning an entry by ID, it it exists.
@@ -200,17 +231,23 @@ impl KfnData {
         }
     }
 
-    /// Removing an entry from the data.
-    pub fn remove_entry_by_id(&mut self, id: usize) {
+    /// Removing an entry from the data. Returns the removed entry.
+    pub fn remove_entry_by_id(&mut self, id: usize) -> Result<Entry, KfnParseError> {
+
+        if id >= self.entries.len() {
+            return Err(KfnParseError::EntryNotFound(id.to_string()));
+        }
         
         // Extract the entry and save it
         // to have it's length later.
         let removed_entry = self.entries.remove(id);
-        // iterate over the entries...
-        for i in id+1..self.entries.len()-1 {
+        // iterate over the entries that came after it...
+        for entry in self.entries.iter_mut().skip(id) {
             // ...and remove the removed entry's length from their offset.
-            self.entries[i].offset -= removed_entry.len1;
+            entry.offset = entry.offset.saturating_sub(removed_entry.len2);
         }
+
+        Ok(removed_entry)
     }
 
     /// Removing an entry by name from the data. If it doesn't exist, it wont delete.
@@ -232,22 +269,14 @@ impl KfnData {
             return;
         }
 
-        for entry in &self.entries {
-            dbg!(&entry.filename);
-        }
         // Extract the entry and save it to have it's length later.
         let removed_entry = self.entries.remove(id as usize);
 
-
-
-        // Iterate over the entries...
-        for i in id as usize+1..self.entries.len()-1 {
+        // Iterate over the entries that came after it...
+        for entry in self.entries.iter_mut().skip(id as usize) {
             // ...and remove the removed entry's length from their offset.
-            self.entries[i as usize].offset -= removed_entry.len1;
+            entry.offset = entry.offset.saturating_sub(removed_entry.len2);
         }
-
-        
-    
     }
 
     /// Gets the next available offset for the new entry.
@@ -260,18 +289,61 @@ impl KfnData {
         // get the id of the last entry
         let last_index = self.entries.len()-1;
 
-        // return the last entry's offset plus its length, removed the end of the dir header to get the new offset
-        self.entries[last_index].offset + self.entries[last_index].len1 - self.offset_dir_end
+        // return the last entry's offset plus its stored length, removed the end of the dir header to get the new offset
+        (self.entries[last_index].offset + self.entries[last_index].len2).saturating_sub(self.offset_dir_end)
     
     }
 
-}
+    /// Writes the directory and the files to binary, encrypting the entries flagged as encrypted with the key.
+    /// If the key is not a valid AES key, the flagged entries are written as plain data.
+    /// Every entry has to be loaded, see `Kfn::write_to` for writing lazily read files.
+    pub fn to_binary_with_key(&mut self, key: &[u8]) -> Result<Vec<u8>, KfnParseError> {
 
-impl ToBinary for KfnData {
-    fn to_binary(&mut self) -> Vec<u8> {
+        if let Some(entry) = self.entries.iter().find(|entry| entry.deferred.is_some()) {
+            return Err(KfnParseError::EntryNotLoaded(entry.filename.clone()));
+        }
+
+        self.prepare_directory(key);
+
+        let mut data: Vec<u8> = Vec::new();
+        self.write_directory(&mut data)?;
+
+        // the payloads are kept decrypted in memory, so they are encrypted here, if needed
+        for entry in &self.entries {
+            crate::write_payload(&mut entry.file_bin.as_slice(), &mut data, crypt::is_encrypted(entry.flags), key, entry)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Calculates the lengths, flags and offsets of the directory before writing, without touching the payloads.
+    /// The entries flagged as encrypted keep the flag only if the key is a valid AES key.
+    pub fn prepare_directory(&mut self, key: &[u8]) {
+
+        let valid_key = key.len() == crypt::BLOCK_SIZE;
+
+        for entry in &mut self.entries {
+
+            // the length of entries not loaded yet is known from the directory
+            entry.len1 = match entry.deferred {
+                Some(location) => location.len1,
+                None => entry.file_bin.len(),
+            };
+
+            if crypt::is_encrypted(entry.flags) && valid_key {
+                entry.len2 = crypt::stored_len(entry.len1);
+            } else {
+                entry.flags &= !crypt::FLAG_ENCRYPTED;
+                entry.len2 = entry.len1;
+            }
+        }
 
         self.adjust_dir_offset();
-        
+    }
+
+    /// Writes the directory, the number of files followed by the entries.
+    pub fn write_directory<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+
         let mut data: Vec<u8> = Vec::new();
         
         data.append(&mut u32_to_u8_arr(self.entries.len() as u32));
@@ -294,14 +366,22 @@ impl ToBinary for KfnData {
             data.append(&mut u32_to_u8_arr(entry.flags as u32));
         }
 
-        // append the file data
-        for entry in &self.entries {
-        
-            data.append(&mut entry.file_bin.to_owned());
-        }
+        writer.write_all(&data)
+    }
+
+    /// Marks the entry to be encrypted or not on export. If it doesn't exist, nothing happens.
+    pub fn set_encrypted(&mut self, name: &str, encrypted: bool) {
+
+        for entry in &mut self.entries {
 
-        //data.append(&mut self.get_songs_ini().unwrap().file_bin);
+            if entry.filename == name {
 
-        data
+                if encrypted {
+                    entry.flags |= crypt::FLAG_ENCRYPTED;
+                } else {
+                    entry.flags &= !crypt::FLAG_ENCRYPTED;
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+}
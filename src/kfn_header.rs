@@ -1,20 +1,36 @@
+pub mod tag;
+
 use std::fmt::Debug;
 use crate::helpers::u32_to_u8_arr;
 use crate::helpers::file_type::ToBinary;
 
+use tag::HeaderTag;
+
+/// The known tags in the order they are written for new files.
+const DEFAULT_TAGS: [&str; 22] = [
+    "DIFM", "DIFW", "GNRE", "SFTV", "MUSL", "ANME", "TYPE", "FLID", "LANG", "TITL", "ARTS",
+    "ALBM", "COMP", "COMM", "COPY", "SORC", "YEAR", "TRAK", "KFNZ", "RGHT", "PROV", "IDUS",
+];
+
 /// Header, containing information about the KFN file. WIP
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KfnHeader {
-    /// Difficulty for men. Value between 1 to 5.
+    /// Difficulty for men. Value between 1 to 5, read files may have 0 if it is not set.
     pub diff_men: u32,  
-    /// Difficulty for women. Value between 1 to 5.
+    /// Difficulty for women. Value between 1 to 5, read files may have 0 if it is not set.
     pub diff_women: u32,
+    /// Genre ID, 0xFFFFFFFF if not set. See `tag::Genre`.
     pub genre: u32,
+    /// SFTV, MUSL and ANME are kept as they are, see `tag::HeaderTag`.
     pub sftv: u32,
     pub musl: u32,
     pub anme: u32,
+    /// Type of the file. See `tag::KfnType`.
     pub kfn_type: u32,
-    pub flid: String,
+    /// The AES key used for encrypting the entries. 16 bytes if present.
+    pub flid: Vec<u8>,
+    /// Language code of the lyrics.
     pub language: String,
     pub album: String,
     pub title: String,
@@ -25,17 +41,275 @@ pub struct KfnHeader {
     pub source_file: String,
     pub year: String,
     pub trak: String,
+    /// RGHT, PROV and IDUS are kept as they are, see `tag::HeaderTag`.
     pub rght: u32,
     pub prov: u32,
     pub karafunizer: String,
     pub idus: String,
+    /// The tags as they were read from the file, in their order.
+    /// Known tags are written from the fields above, the rest as they were.
+    pub tags: Vec<RawTag>,
+}
+
+impl Default for KfnHeader {
+    /// An empty header with no genre set, and the lowest difficulties.
+    fn default() -> Self {
+        Self {
+            diff_men: 1,
+            diff_women: 1,
+            genre: u32::MAX,
+            sftv: 0,
+            musl: 0,
+            anme: 0,
+            kfn_type: 0,
+            flid: Vec::new(),
+            language: String::new(),
+            album: String::new(),
+            title: String::new(),
+            artist: String::new(),
+            composer: String::new(),
+            comment: String::new(),
+            copyright: String::new(),
+            source_file: String::new(),
+            year: String::new(),
+            trak: String::new(),
+            rght: 0,
+            prov: 0,
+            karafunizer: String::new(),
+            idus: String::new(),
+            tags: Vec::new(),
+        }
+    }
 }
 
+/// A header tag as it was read from the file.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawTag {
+    /// The four character signature of the tag.
+    pub signature: String,
+    pub value: TagValue,
+}
+
+/// The value of a header tag, depending on its type.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TagValue {
+    /// Type 1, the value is stored in the tag.
+    Dword(u32),
+    /// Type 2, the data follows the tag.
+    Data(Vec<u8>),
+    /// Any other type, with the value stored in the tag.
+    Other(u8, u32),
+}
 
 impl KfnHeader {
 
-    
+    /// Stores a tag read from the file, setting the field of the known ones.
+    pub fn read_tag(&mut self, signature: &str, value: TagValue) {
+
+        self.set_field(signature, &value);
+
+        self.tags.push(RawTag { signature: signature.to_string(), value });
+    }
+
+    /// Returns every tag of the header as it would be written, in order, typed where possible.
+    pub fn header_tags(&self) -> Vec<HeaderTag> {
+        self.raw_tags().iter().map(HeaderTag::from).collect()
+    }
+
+    /// Returns the tag with the signature, if it would be written.
+    pub fn get_tag(&self, signature: &str) -> Option<HeaderTag> {
+        self.header_tags().into_iter().find(|tag| tag.signature() == signature)
+    }
+
+    /// Sets a tag. Known tags set their field, unknown ones replace the tag with the same signature,
+    /// or get added to the end of the header.
+    pub fn set_tag(&mut self, tag: HeaderTag) {
+
+        let raw = RawTag::from(&tag);
+
+        self.set_field(&raw.signature, &raw.value);
+
+        // the known tags of new files are written from the fields alone
+        if !self.is_read() && self.field_value(&raw.signature).is_some() {
+            return;
+        }
+
+        match self.tags.iter_mut().find(|tag| tag.signature == raw.signature) {
+            Some(tag) => *tag = raw,
+            None => {
+                let end = self.tags.iter().position(|tag| tag.signature == "ENDH").unwrap_or(self.tags.len());
+                self.tags.insert(end, raw);
+            },
+        }
+    }
+
+    /// Returns true, if the header was read from a file, which always ends with an ENDH tag.
+    fn is_read(&self) -> bool {
+        self.tags.iter().any(|tag| tag.signature == "ENDH")
+    }
+
+    /// Sets the field of a known tag from its value. Unknown tags are ignored.
+    fn set_field(&mut self, signature: &str, value: &TagValue) {
+
+        match value {
+            TagValue::Dword(value) => {
+                match signature {
+                    "DIFM" => self.diff_men = *value,
+                    "DIFW" => self.diff_women = *value,
+                    "GNRE" => self.genre = *value,
+                    "SFTV" => self.sftv = *value,
+                    "MUSL" => self.musl = *value,
+                    "ANME" => self.anme = *value,
+                    "TYPE" => self.kfn_type = *value,
+                    "RGHT" => self.rght = *value,
+                    "PROV" => self.prov = *value,
+                    _ => (),
+                }
+            },
+            TagValue::Data(buffer) => {
+                let buffer_str = String::from_utf8(buffer.clone()).unwrap_or("Unknown".to_string());
+
+                match signature {
+                    // the key is binary, so keep the raw bytes
+                    "FLID" => self.flid = buffer.clone(),
+                    "LANG" => self.language = buffer_str,
+                    "TITL" => self.title = buffer_str,
+                    "ARTS" => self.artist = buffer_str,
+                    "ALBM" => self.album = buffer_str,
+                    "COMP" => self.composer = buffer_str,
+                    "COMM" => self.comment = buffer_str,
+                    "COPY" => self.copyright = buffer_str,
+                    "SORC" => self.source_file = buffer_str,
+                    "YEAR" => self.year = buffer_str,
+                    "TRAK" => self.trak = buffer_str,
+                    "KFNZ" => self.karafunizer = buffer_str,
+                    "IDUS" => self.idus = buffer_str,
+                    _ => (),
+                }
+            },
+            TagValue::Other(_, _) => (),
+        }
+    }
+
+    /// Returns the current value of a known tag from the fields, or None for unknown tags.
+    fn field_value(&self, signature: &str) -> Option<TagValue> {
 
+        let value = match signature {
+            "DIFM" => TagValue::Dword(self.diff_men),
+            "DIFW" => TagValue::Dword(self.diff_women),
+            "GNRE" => TagValue::Dword(self.genre),
+            "SFTV" => TagValue::Dword(self.sftv),
+            "MUSL" => TagValue::Dword(self.musl),
+            "ANME" => TagValue::Dword(self.anme),
+            "TYPE" => TagValue::Dword(self.kfn_type),
+            "RGHT" => TagValue::Dword(self.rght),
+            "PROV" => TagValue::Dword(self.prov),
+            "FLID" => TagValue::Data(self.flid.clone()),
+            "LANG" => TagValue::Data(self.language.as_bytes().to_owned()),
+            "TITL" => TagValue::Data(self.title.as_bytes().to_owned()),
+            "ARTS" => TagValue::Data(self.artist.as_bytes().to_owned()),
+            "ALBM" => TagValue::Data(self.album.as_bytes().to_owned()),
+            "COMP" => TagValue::Data(self.composer.as_bytes().to_owned()),
+            "COMM" => TagValue::Data(self.comment.as_bytes().to_owned()),
+            "COPY" => TagValue::Data(self.copyright.as_bytes().to_owned()),
+            "SORC" => TagValue::Data(self.source_file.as_bytes().to_owned()),
+            "YEAR" => TagValue::Data(self.year.as_bytes().to_owned()),
+            "TRAK" => TagValue::Data(self.trak.as_bytes().to_owned()),
+            "KFNZ" => TagValue::Data(self.karafunizer.as_bytes().to_owned()),
+            "IDUS" => TagValue::Data(self.idus.as_bytes().to_owned()),
+            _ => return None,
+        };
+
+        Some(value)
+    }
+
+    /// Returns the value to write for a tag read from the file.
+    /// If the field was not changed since reading, the original value is kept, so nothing is lost in conversion.
+    fn tag_value(&self, tag: &RawTag) -> TagValue {
+
+        let current = match self.field_value(&tag.signature) {
+            Some(current) => current,
+            None => return tag.value.clone(),
+        };
+
+        let mut original = KfnHeader::default();
+        original.set_field(&tag.signature, &tag.value);
+
+        match original.field_value(&tag.signature) == Some(current.clone()) {
+            true => tag.value.clone(),
+            false => current,
+        }
+    }
+}
+
+/// Appends a tag to the binary header.
+fn write_tag(data: &mut Vec<u8>, signature: &str, value: &TagValue) {
+
+    data.append(&mut signature.as_bytes().to_owned());
+
+    match value {
+        TagValue::Dword(value) => {
+            data.push(1_u8);
+            data.append(&mut u32_to_u8_arr(*value));
+        },
+        TagValue::Data(buffer) => {
+            data.push(2_u8);
+            data.append(&mut u32_to_u8_arr(buffer.len() as u32));
+            data.append(&mut buffer.clone());
+        },
+        TagValue::Other(l_type, value) => {
+            data.push(*l_type);
+            data.append(&mut u32_to_u8_arr(*value));
+        },
+    }
+}
+
+impl KfnHeader {
+    /// Returns the tags to write, including the closing ENDH tag.
+    /// Read files keep the order and the unknown tags, known tags set since reading are added before the end.
+    /// New files get every known tag, followed by the unknown ones.
+    fn raw_tags(&self) -> Vec<RawTag> {
+
+        let read = self.is_read();
+        let mut tags: Vec<RawTag> = Vec::new();
+
+        if read {
+            for tag in self.tags.iter().filter(|tag| tag.signature != "ENDH") {
+                tags.push(RawTag { signature: tag.signature.clone(), value: self.tag_value(tag) });
+            }
+        }
+
+        let empty = KfnHeader::default();
+        for signature in DEFAULT_TAGS {
+
+            if self.tags.iter().any(|tag| tag.signature == signature) {
+                continue;
+            }
+
+            let value = match self.field_value(signature) {
+                Some(value) => value,
+                None => continue,
+            };
+            if !read || Some(&value) != empty.field_value(signature).as_ref() {
+                tags.push(RawTag { signature: signature.to_string(), value });
+            }
+        }
+
+        if !read {
+            tags.extend(self.tags.iter().cloned());
+        }
+
+        // END HEADER
+        let end = match self.tags.iter().find(|tag| tag.signature == "ENDH") {
+            Some(tag) => tag.value.clone(),
+            None => TagValue::Dword(u32::MAX),
+        };
+        tags.push(RawTag { signature: "ENDH".to_string(), value: end });
+
+        tags
+    }
 }
 
 impl ToBinary for KfnHeader {
@@ -47,112 +321,11 @@ impl ToBinary for KfnHeader {
 
         // beginning of header
         data.append(&mut "KFNB".as_bytes().to_owned());
-        // men difficulty
-        data.append(&mut "DIFM".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.diff_men));
-        // woman difficulty 
-        data.append(&mut "DIFW".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.diff_women));
-        // genre
-        data.append(&mut "GNRE".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.genre));
-        // sftv
-        data.append(&mut "SFTV".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.sftv));
-        // musl
-        data.append(&mut "MUSL".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.musl));
-        // anme
-        data.append(&mut "ANME".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.anme));
-        // type
-        data.append(&mut "TYPE".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.kfn_type));
-        // flid - encryption key
-        data.append(&mut "FLID".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.flid.len() as u32));
-        data.append(&mut self.flid.as_bytes().to_owned());
-        // language
-        data.append(&mut "LANG".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.language.len() as u32));
-        data.append(&mut self.language.as_bytes().to_owned());
-        // title
-        data.append(&mut "TITL".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.title.len() as u32));
-        data.append(&mut self.title.as_bytes().to_owned());
-        // artist
-        data.append(&mut "ARTS".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.artist.len() as u32));
-        data.append(&mut self.artist.as_bytes().to_owned());
-        // album
-        data.append(&mut "ALBM".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.album.len() as u32));
-        data.append(&mut self.album.as_bytes().to_owned());
-        // composer
-        data.append(&mut "COMP".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.composer.len() as u32));
-        data.append(&mut self.composer.as_bytes().to_owned());
-        // comment
-        data.append(&mut "COMM".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.comment.len() as u32));
-        data.append(&mut self.comment.as_bytes().to_owned());
-        // copyright
-        data.append(&mut "COPY".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.copyright.len() as u32));
-        data.append(&mut self.copyright.as_bytes().to_owned());
-        // source
-        data.append(&mut "SORC".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.source_file.len() as u32));
-        data.append(&mut self.source_file.as_bytes().to_owned());
-        // year
-        data.append(&mut "YEAR".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.year.len() as u32));
-        data.append(&mut self.year.as_bytes().to_owned());
-        // track / trak
-        data.append(&mut "TRAK".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.trak.len() as u32));
-        data.append(&mut self.trak.as_bytes().to_owned());
-        // karafunizer
-        data.append(&mut "KFNZ".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.karafunizer.len() as u32));
-        data.append(&mut self.karafunizer.as_bytes().to_owned());
-        // rght / right
-        data.append(&mut "RGHT".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.rght));
-        // prov / 
-        data.append(&mut "PROV".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut u32_to_u8_arr(self.prov));
-        // IDUS 
-        data.append(&mut "IDUS".as_bytes().to_owned());
-        data.push(2_u8);
-        data.append(&mut u32_to_u8_arr(self.idus.len() as u32));
-        data.append(&mut self.idus.as_bytes().to_owned());
-        // END HEADER
-        data.append(&mut "ENDH".as_bytes().to_owned());
-        data.push(1_u8);
-        data.append(&mut vec![255, 255, 255, 255]);
+
+        for tag in self.raw_tags() {
+            write_tag(&mut data, &tag.signature, &tag.value);
+        }
 
         data
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,260 @@
+use crate::kfn_header::{RawTag, TagValue};
+
+/// A header tag, with the value typed for the known ones.
+/// Tags with an unknown signature, or a value that doesn't fit the type, are kept raw.
+/// SFTV, MUSL, ANME, RGHT, PROV and IDUS are known by their type, but their meaning isn't documented,
+/// so they keep their plain value, and are written back unchanged.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderTag {
+    /// DIFM, difficulty for men.
+    DiffMen(Option<Difficulty>),
+    /// DIFW, difficulty for women.
+    DiffWomen(Option<Difficulty>),
+    /// GNRE
+    Genre(Genre),
+    /// SFTV, likely the version of the software that wrote the file.
+    Sftv(u32),
+    /// MUSL, likely the length of the music in seconds.
+    Musl(u32),
+    /// ANME, meaning unknown.
+    Anme(u32),
+    /// TYPE
+    KfnType(KfnType),
+    /// FLID, the AES key of the encrypted entries.
+    Flid(Vec<u8>),
+    /// LANG, the language code of the lyrics.
+    Language(String),
+    /// TITL
+    Title(String),
+    /// ARTS
+    Artist(String),
+    /// ALBM
+    Album(String),
+    /// COMP
+    Composer(String),
+    /// COMM
+    Comment(String),
+    /// COPY
+    Copyright(String),
+    /// SORC, the source of the music.
+    Source(String),
+    /// YEAR
+    Year(String),
+    /// TRAK
+    Track(String),
+    /// KFNZ
+    Karafunizer(String),
+    /// RGHT, meaning unknown.
+    Rght(u32),
+    /// PROV, meaning unknown.
+    Prov(u32),
+    /// IDUS, meaning unknown.
+    Idus(String),
+    /// ENDH, the end of the header.
+    End(u32),
+    /// Any other tag, as it was in the file.
+    Unknown(RawTag),
+}
+
+impl HeaderTag {
+    /// The four character signature of the tag.
+    pub fn signature(&self) -> &str {
+        match self {
+            HeaderTag::DiffMen(_) => "DIFM",
+            HeaderTag::DiffWomen(_) => "DIFW",
+            HeaderTag::Genre(_) => "GNRE",
+            HeaderTag::Sftv(_) => "SFTV",
+            HeaderTag::Musl(_) => "MUSL",
+            HeaderTag::Anme(_) => "ANME",
+            HeaderTag::KfnType(_) => "TYPE",
+            HeaderTag::Flid(_) => "FLID",
+            HeaderTag::Language(_) => "LANG",
+            HeaderTag::Title(_) => "TITL",
+            HeaderTag::Artist(_) => "ARTS",
+            HeaderTag::Album(_) => "ALBM",
+            HeaderTag::Composer(_) => "COMP",
+            HeaderTag::Comment(_) => "COMM",
+            HeaderTag::Copyright(_) => "COPY",
+            HeaderTag::Source(_) => "SORC",
+            HeaderTag::Year(_) => "YEAR",
+            HeaderTag::Track(_) => "TRAK",
+            HeaderTag::Karafunizer(_) => "KFNZ",
+            HeaderTag::Rght(_) => "RGHT",
+            HeaderTag::Prov(_) => "PROV",
+            HeaderTag::Idus(_) => "IDUS",
+            HeaderTag::End(_) => "ENDH",
+            HeaderTag::Unknown(raw) => &raw.signature,
+        }
+    }
+}
+
+impl From<&RawTag> for HeaderTag {
+    fn from(raw: &RawTag) -> Self {
+
+        let unknown = || HeaderTag::Unknown(raw.clone());
+
+        match &raw.value {
+            TagValue::Dword(value) => {
+                let value = *value;
+                match raw.signature.as_str() {
+                    // 0 means the difficulty is not set
+                    "DIFM" if value == 0 => HeaderTag::DiffMen(None),
+                    "DIFW" if value == 0 => HeaderTag::DiffWomen(None),
+                    "DIFM" => match Difficulty::new(value) {
+                        Some(difficulty) => HeaderTag::DiffMen(Some(difficulty)),
+                        None => unknown(),
+                    },
+                    "DIFW" => match Difficulty::new(value) {
+                        Some(difficulty) => HeaderTag::DiffWomen(Some(difficulty)),
+                        None => unknown(),
+                    },
+                    "GNRE" => HeaderTag::Genre(Genre::from(value)),
+                    "SFTV" => HeaderTag::Sftv(value),
+                    "MUSL" => HeaderTag::Musl(value),
+                    "ANME" => HeaderTag::Anme(value),
+                    "TYPE" => HeaderTag::KfnType(KfnType::from(value)),
+                    "RGHT" => HeaderTag::Rght(value),
+                    "PROV" => HeaderTag::Prov(value),
+                    "ENDH" => HeaderTag::End(value),
+                    _ => unknown(),
+                }
+            },
+            TagValue::Data(data) => {
+                // the key is binary, every other known data tag is text
+                if raw.signature == "FLID" {
+                    return HeaderTag::Flid(data.clone());
+                }
+                let text = match String::from_utf8(data.clone()) {
+                    Ok(text) => text,
+                    Err(_) => return unknown(),
+                };
+                match raw.signature.as_str() {
+                    "LANG" => HeaderTag::Language(text),
+                    "TITL" => HeaderTag::Title(text),
+                    "ARTS" => HeaderTag::Artist(text),
+                    "ALBM" => HeaderTag::Album(text),
+                    "COMP" => HeaderTag::Composer(text),
+                    "COMM" => HeaderTag::Comment(text),
+                    "COPY" => HeaderTag::Copyright(text),
+                    "SORC" => HeaderTag::Source(text),
+                    "YEAR" => HeaderTag::Year(text),
+                    "TRAK" => HeaderTag::Track(text),
+                    "KFNZ" => HeaderTag::Karafunizer(text),
+                    "IDUS" => HeaderTag::Idus(text),
+                    _ => unknown(),
+                }
+            },
+            TagValue::Other(_, _) => unknown(),
+        }
+    }
+}
+
+impl From<&HeaderTag> for RawTag {
+    fn from(tag: &HeaderTag) -> Self {
+
+        let value = match tag {
+            HeaderTag::DiffMen(difficulty) | HeaderTag::DiffWomen(difficulty) => 
+                TagValue::Dword(difficulty.map(|difficulty| difficulty.value()).unwrap_or(0)),
+            HeaderTag::Genre(genre) => TagValue::Dword((*genre).into()),
+            HeaderTag::KfnType(kfn_type) => TagValue::Dword((*kfn_type).into()),
+            HeaderTag::Sftv(value) | HeaderTag::Musl(value) | HeaderTag::Anme(value) 
+            | HeaderTag::Rght(value) | HeaderTag::Prov(value) | HeaderTag::End(value) => TagValue::Dword(*value),
+            HeaderTag::Flid(key) => TagValue::Data(key.clone()),
+            HeaderTag::Language(text) | HeaderTag::Title(text) | HeaderTag::Artist(text)
+            | HeaderTag::Album(text) | HeaderTag::Composer(text) | HeaderTag::Comment(text)
+            | HeaderTag::Copyright(text) | HeaderTag::Source(text) | HeaderTag::Year(text)
+            | HeaderTag::Track(text) | HeaderTag::Karafunizer(text) | HeaderTag::Idus(text) => 
+                TagValue::Data(text.as_bytes().to_owned()),
+            HeaderTag::Unknown(raw) => return raw.clone(),
+        };
+
+        RawTag { signature: tag.signature().to_string(), value }
+    }
+}
+
+/// Difficulty of the song, between 1 and 5.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Difficulty(u8);
+
+impl Difficulty {
+    /// Creates a difficulty, if the value is between 1 and 5.
+    pub fn new(value: u32) -> Option<Self> {
+        match value {
+            1..=5 => Some(Self(value as u8)),
+            _ => None,
+        }
+    }
+
+    /// The difficulty as a number between 1 and 5.
+    pub fn value(&self) -> u32 {
+        self.0 as u32
+    }
+}
+
+/// Genre of the song, stored as the ID of the genre in KaraFun.
+/// The names of the IDs are defined by KaraFun, not by the file, so only the ID is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Genre {
+    /// No genre set, stored as 0xFFFFFFFF, or -1 in the Song.ini.
+    #[default]
+    None,
+    /// The ID of the genre.
+    Id(u32),
+}
+
+impl From<u32> for Genre {
+    fn from(value: u32) -> Self {
+        match value {
+            u32::MAX => Genre::None,
+            id => Genre::Id(id),
+        }
+    }
+}
+
+impl From<Genre> for u32 {
+    fn from(genre: Genre) -> Self {
+        match genre {
+            Genre::None => u32::MAX,
+            Genre::Id(id) => id,
+        }
+    }
+}
+
+impl std::fmt::Display for Genre {
+    /// Writes the genre as the GenreID of the Song.ini.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Genre::None => write!(f, "-1"),
+            Genre::Id(id) => write!(f, "{}", id),
+        }
+    }
+}
+
+/// Type of the KFN file.
+/// Only the regular type is documented, the other values are kept as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum KfnType {
+    /// Type 0, a regular karaoke file.
+    #[default]
+    Standard,
+    /// Any other type, by its value.
+    Other(u32),
+}
+
+impl From<u32> for KfnType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => KfnType::Standard,
+            value => KfnType::Other(value),
+        }
+    }
+}
+
+impl From<KfnType> for u32 {
+    fn from(kfn_type: KfnType) -> Self {
+        match kfn_type {
+            KfnType::Standard => 0,
+            KfnType::Other(value) => value,
+        }
+    }
+}
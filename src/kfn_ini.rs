@@ -1,25 +1,65 @@
 pub mod eff;
+pub mod general;
 pub mod trajectory;
 
-use ini::Ini;
+use ini::{Ini, Properties};
 
 use eff::{AnimEntry, Eff, Effect, Action, TransType, Anim};
 
 use trajectory::Trajectory;
 
 use crate::kfn_header::KfnHeader;
+use crate::kfn_header::tag::Genre;
 
 use crate::helpers::Entry;
 use crate::kfn_ini::eff::TextEntry;
+use crate::KfnParseError;
 
+use general::{General, Mp3Music, Mp3Track, is_numbered};
+
+
+/// Keys of the Eff# sections represented by the fields of `Eff`, besides the numbered ones.
+const EFF_KEYS: [&str; 9] = ["ID", "NbAnim", "TextCount", "Trajectory", "LibImage", "VideoFile", "Font", "ActiveColor", "InactiveColor"];
+
+/// Number of syncs written in one Sync# line.
+const SYNCS_PER_LINE: usize = 10;
+
+/// Serializing the INI as its text.
+#[cfg(feature = "serde")]
+mod ini_text {
+    use ini::Ini;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error as _, ser::Error as _};
+
+    pub fn serialize<S: Serializer>(ini: &Ini, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut text: Vec<u8> = Vec::new();
+        ini.write_to(&mut text).map_err(S::Error::custom)?;
+        serializer.serialize_str(&String::from_utf8(text).map_err(S::Error::custom)?)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Ini, D::Error> {
+        Ini::load_from_str(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+}
 
 /// The Song.ini file, which is at the very end of a .kfn file.
 /// Contains most of the information for replicating a karaoke video.
+/// 
+/// After loading, the typed sections are the ones to edit, the INI is recreated from them by `write_ini`.
+/// Sections not represented here are kept in the INI as they were.
 #[derive(Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct KfnIni {
     /// The Song.ini file itself, represented using the ini-rust library.
     /// To learn more: https://github.com/zonyitoo/rust-ini
+    /// Serialized as its text, so the sections not represented below are kept.
+    #[cfg_attr(feature = "serde", serde(with = "ini_text"))]
     pub ini: Ini,
+    /// The General section, with the metadata of the song.
+    pub general: General,
+    /// The Materials section, the list of the files in the KFN.
+    pub materials: Vec<String>,
+    /// The MP3Music section, with the additional tracks.
+    pub mp3_music: Mp3Music,
     /// Representation of the various effects, texts and syncs.
     pub effs: Vec<Eff>,
 }
@@ -27,124 +67,136 @@ pub struct KfnIni {
 impl KfnIni {
     /// Creating a new ini file.
     pub fn new() -> Self {
-        Self { ini: Ini::new(), effs: Vec::new(), }
+        Self { ini: Ini::new(), ..Default::default() }
     }
 
     /// Populating the General section with empty data.
     pub fn populate_empty(&mut self) {
 
-        self.ini.with_section(Some("General"))
-            .set("Title", "")
-            .set("Artist", "")
-            .set("Album", "")
-            .set("Composer", "")
-            .set("Year", "")
-            .set("Track", "")
-            .set("GenreID", "-1")
-            .set("Copyright", "")
-            .set("Comment", "")
-            .set("Source", "")
-            .set("EffectCount", "")
-            .set("LanguageID", "")
-            .set("DiffMen", "")
-            .set("DiffWomen", "")
-            .set("KFNType", "0")
-            .set("Properties", "")
-            .set("KaraokeVersion", "")
-            .set("VocalGuide", "")
-            .set("KaraFunization", "");
+        self.general = General::default();
 
+        for (key, value) in self.general.to_pairs(self.effs.len()) {
+            self.ini.with_section(Some("General")).set(key, value);
+        }
     }
 
     /// Returns the secondary source / vocal included track, if it exists.
     pub fn get_secondary_source(&self) -> Option<String> {
-        if self.ini.get_from(Some("MP3Music"), "Track0") != None {
-            let value = self.ini.get_from(Some("MP3Music"), "Track0").unwrap();
-            Some(
-                self.ini.get_from(Some("MP3Music"), "Track0").unwrap()[..value.rfind(".mp3").unwrap()+4].to_string()
-            )
-        } else {
-            None
+        let track = self.mp3_music.tracks.first()?;
+
+        // the player only handles mp3 files
+        match track.filename.ends_with(".mp3") {
+            true => Some(track.filename.clone()),
+            false => None,
         }
     }
 
+    /// Returns true, if the secondary source replaces the main track, instead of being played along with it.
     pub fn replaces_track(&self) -> bool {
-        if self.ini.get_from(Some("MP3Music"), "Track0") != None {
-            if self.ini.get_from(Some("MP3Music"), "Track0").unwrap().split(',').collect::<Vec<&str>>()[2] == "0" {
-                return false;
-            } else {
-                return true;
-            }
+        match self.mp3_music.tracks.first() {
+            Some(track) => track.replaces_track(),
+            None => false,
         }
-        false
     }
 
-    /// Populating the General section with empty data.
+    /// Populating the General section from the header.
     pub fn populate_from_header(&mut self, header: &KfnHeader) {
 
         let mut source = String::new();
-        if header.source_file.len() > 4 {
-            if &header.source_file[0..=3] != "1,I," {
-                source.push_str("1,I,");
-            }
+        if !header.source_file.is_empty() && !header.source_file.starts_with("1,I,") {
+            source.push_str("1,I,");
         }
         
         source.push_str(&header.source_file);
 
-        
+        let difficulty = |value: u32| match value {
+            0 => None,
+            value => Some(value),
+        };
+
+        self.general.title = header.title.clone();
+        self.general.artist = header.artist.clone();
+        self.general.album = header.album.clone();
+        self.general.composer = header.composer.clone();
+        self.general.year = header.year.clone();
+        self.general.track = header.trak.clone();
+        self.general.genre_id = match Genre::from(header.genre) {
+            Genre::None => -1,
+            Genre::Id(id) => id as i32,
+        };
+        self.general.copyright = header.copyright.clone();
+        self.general.comment = header.comment.clone();
+        self.general.source = source;
+        self.general.language_id = header.language.clone();
+        self.general.diff_men = difficulty(header.diff_men);
+        self.general.diff_women = difficulty(header.diff_women);
+        self.general.kfn_type = header.kfn_type;
+        self.general.karafunization = header.karafunizer.clone();
+    }
+
+    /// Reading every section into the typed representation.
+    pub fn load(&mut self) -> Result<(), KfnParseError> {
+
+        self.general = match self.ini.section(Some("General")) {
+            Some(section) => General::from_section(section)?,
+            None => General::default(),
+        };
+
+        self.materials = Vec::new();
+        if let Some(section) = self.ini.section(Some("Materials")) {
+            for (key, value) in section.iter() {
+                if is_numbered(key, "Mat") {
+                    self.materials.push(value.to_string());
+                }
+            }
+        }
 
-        self.ini.with_section(Some("General"))
-            .set("Title", &header.title)
-            .set("Artist", &header.artist)
-            .set("Album", &header.album)
-            .set("Composer", &header.composer)
-            .set("Year", &header.year)
-            .set("Track", &header.trak)
-            .set("GenreID", &header.genre.to_string())
-            .set("Copyright", &header.copyright)
-            .set("Comment", "")
-            .set("Source", source)
-            .set("EffectCount", "")
-            .set("LanguageID", &header.language)
-            .set("DiffMen", header.diff_men.to_string())
-            .set("DiffWomen", header.diff_women.to_string())
-            .set("KFNType", header.kfn_type.to_string())
-            .set("Properties", "")
-            .set("KaraokeVersion", "")
-            .set("VocalGuide", "")
-            .set("KaraFunization", &header.karafunizer);
+        self.mp3_music = match self.ini.section(Some("MP3Music")) {
+            Some(section) => Mp3Music::from_section(section),
+            None => Mp3Music::default(),
+        };
 
+        self.load_eff()
     }
 
     /// Reading the Eff# headed sections
-    pub fn load_eff(&mut self) {
-        
-        let mut text_count_all = 0;
+    pub fn load_eff(&mut self) -> Result<(), KfnParseError> {
+
+        self.effs.clear();
+
+        // get the number of effects to parse, an empty value means there are none
+        let effect_count = match self.ini.get_from(Some("General"), "EffectCount").unwrap_or("0") {
+            "" => 0,
+            value => parse_value::<usize>("General", "EffectCount", value)?,
+        };
 
-        // get the number of effects to parse
-        let effect_count = self.ini.get_from(Some("General"), "EffectCount").unwrap_or("0").to_string().parse::<usize>().unwrap();
-        dbg!(&effect_count);
         // based on the number of effects...
         for eff_num in 1..=effect_count {
             // create a string "Eff#" 
             let eff = format!("Eff{n}", n = &eff_num);
             
             // select the Eff# section based on the string we previously constructed
-            let section = self.ini.section(Some(eff)).unwrap();
+            let section = match self.ini.section(Some(eff.as_str())) {
+                Some(section) => section,
+                None => return Err(KfnParseError::MissingIniSection(eff)),
+            };
+
+            // helper for the mandatory keys
+            let get = |key: &str| match section.get(key) {
+                Some(value) => Ok(value),
+                None => Err(KfnParseError::MissingIniValue { section: eff.clone(), key: key.to_string() }),
+            };
             
             // TODO implement the rest of the properties
-            let id = section.get("ID").unwrap().to_string().parse::<usize>().unwrap();
+            let id = parse_value::<usize>(&eff, "ID", get("ID")?)?;
 
             // number of animations
-            let nb_anim = section.get("NbAnim").unwrap().to_string().parse::<usize>().unwrap();
+            let nb_anim = parse_value::<usize>(&eff, "NbAnim", get("NbAnim")?)?;
             // number of text lines
-            let text_count = section.get("TextCount").unwrap_or("0").to_string().parse::<usize>().unwrap();
-            text_count_all += text_count;
+            let text_count = parse_value::<usize>(&eff, "TextCount", section.get("TextCount").unwrap_or("0"))?;
 
             // starting trajectory
-            let initial_trajectory = Trajectory::from(
-                section.get("Trajectory").unwrap_or_default()
-            );
+            let initial_trajectory = parse_value::<Trajectory>(&eff, "Trajectory", section.get("Trajectory").unwrap_or_default())?;
             // looking for initial library image
             let initial_lib_image = match section.get("LibImage") {
                 Some(s) => {
@@ -158,12 +210,7 @@ impl KfnIni {
                 None => None,
             };
 
-            let initial_inactive_color = match section.get("InactiveColor") {
-                Some(s) => {
-                    Some(s.to_string())
-                },
-                None => None
-            };
+            let initial_inactive_color = section.get("InactiveColor").map(|s| s.to_string());
 
             // looking for initial video file
             let initial_video_file = match section.get("VideoFile") {
@@ -177,149 +224,142 @@ impl KfnIni {
                 },
                 None => None,
             };
-            // looking for initial font
+            // keys not represented by the fields are kept as they are
+            let mut extra: Vec<(String, String)> = section.iter()
+                .filter(|(key, _)| !EFF_KEYS.contains(key) && !is_numbered(key, "Anim") && !is_numbered(key, "Sync") && !is_numbered(key, "Text"))
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+
+            // looking for initial font, in the form of name*size
             let initial_font: Option<(String, u32)> = match section.get("Font") {
+                Some("") | None => None,
                 Some(s) => {
-                    let res: Vec<&str> = s.split("*").collect();
-                    dbg!(&res);
+                    let res: Vec<&str> = s.split('*').collect();
                     let filename = res[0];
-                    let extension = &filename[filename.len()-4..filename.len()];
-                    if extension == ".ttf" || extension == ".TTF" || extension == ".otf" {
-                        Some((res[0].to_string(), u32::from_str_radix(res[1], 10).unwrap()))
-                    } else {
-                        None
+                    let extension = filename.to_lowercase();
+                    match res.get(1) {
+                        Some(size) => Some((filename.to_string(), parse_value::<u32>(&eff, "Font", size)?)),
+                        // embedded fonts need a size
+                        None if extension.ends_with(".ttf") || extension.ends_with(".otf") => {
+                            return Err(KfnParseError::InvalidIniValue { 
+                                section: eff, key: "Font".to_string(), value: s.to_string() 
+                            });
+                        },
+                        None => {
+                            extra.push(("Font".to_string(), s.to_string()));
+                            None
+                        },
                     }
                 },
-                // if none, revert to Arial Black, as that is the default in the original program
-                None => {
-                    None
-                    //("Arial Black".to_string(), 12)
-                }
             };
-            dbg!(&initial_font);
 
-            let initial_active_color = match section.get("ActiveColor") {
-                Some(s) => {
-                    Some(s.to_string())
-                },
-                None => None
-            };
+            let initial_active_color = section.get("ActiveColor").map(|s| s.to_string());
 
 
             // list of animations in Anim# form
             let mut anims: Vec<Anim> = Vec::new();
             let mut syncs: Vec<usize> = Vec::new();
             let mut texts: Vec<TextEntry> = Vec::new();
-            //let mut texts: Vec<String> = Vec::new();
             
-            dbg!(nb_anim);
             // reading the animations, if there are any.
-            if nb_anim != 0 {
-                for j in 0..nb_anim {
+            for j in 0..nb_anim {
 
-                    // create a vector for the AnimEntries
-                    let mut anim_entries: Vec<AnimEntry> = Vec::new();
+                // create a vector for the AnimEntries
+                let mut anim_entries: Vec<AnimEntry> = Vec::new();
 
-                    // construct the key with the proper number
-                    let key = format!("Anim{n}", n = &j);
+                // construct the key with the proper number
+                let key = format!("Anim{n}", n = &j);
 
+                let value = get(&key)?;
 
-                    let value = section.get(key).unwrap();
-                    
-                    // the time in ms, when the anim occurs. The first one will always be the time.
-                    let time = value
-                                            .split('|')
-                                            .collect::<Vec<&str>>()
-                                            [0]
-                                            .parse::<usize>()
-                                            .unwrap();
-                    let remaining: Vec<&str> = value
-                                                    .split('|').collect::<Vec<&str>>()
-                                                    .split_first()
-                                                    .unwrap()
-                                                    .1
-                                                    .to_owned();
+                let invalid = || KfnParseError::InvalidIniValue { 
+                    section: eff.clone(), key: key.clone(), value: value.to_string() 
+                };
+                
+                // the time in centiseconds, when the anim occurs. The first one will always be the time.
+                let mut parts = value.split('|');
+                let time = parts.next().unwrap_or_default().parse::<usize>().map_err(|_| invalid())?;
+                
+                for part in parts {
+                    let tokens: Vec<&str> = part.split(',').collect();
+
+                    // first one is always the action, kept as it is if its value is malformed
+                    let action = match tokens[0].parse::<Action>() {
+                        Ok(action) => action,
+                        Err(_) => {
+                            let (key, value) = tokens[0].split_once('=').ok_or_else(invalid)?;
+                            Action::Unknown(key.to_string(), value.to_string())
+                        },
+                    };
                     
-                    for i in 0..remaining.len() {
-                        let tokens: Vec<&str> = remaining[i].split(',').collect();
-
-                        // first one is always the action
-                        let action = Action::from(tokens[0]);
-                        
-                        let mut effect: Option<Effect> = None;
-                        let mut trans_time: f64 = 0.0;
-                        let mut trans_type = TransType::default();
-
-                        for j in 0..tokens.len() {
-                            let key = tokens[j].split('=').collect::<Vec<&str>>()[0];
-                            let value = tokens[j].split('=').collect::<Vec<&str>>()[1];
-                            match key  {
-                                "Effect" => effect = Some(Effect::from(value)),
-                                "TransitionTime" => trans_time = value.parse().unwrap(),
-                                "TransitionType" => trans_type = TransType::from(value),
-                                &_ => ()
-                            }
+                    let mut effect: Option<Effect> = None;
+                    let mut trans_time: Option<f64> = None;
+                    let mut trans_type = TransType::default();
+                    let mut extra: Vec<(String, String)> = Vec::new();
+
+                    for token in &tokens[1..] {
+                        let (key, value) = token.split_once('=').ok_or_else(invalid)?;
+                        match key  {
+                            "Effect" => effect = Some(Effect::from(value)),
+                            "TransitionTime" => trans_time = Some(value.parse().map_err(|_| invalid())?),
+                            "TransitionType" => trans_type = TransType::from(value),
+                            &_ => extra.push((key.to_string(), value.to_string())),
                         }
-
-                        let anim_entry = AnimEntry { action, effect, trans_time, trans_type };
-                        anim_entries.push(anim_entry)
                     }
-                    anims.push(Anim {time, anim_entries});
-                } // for j in 0..nb_anim {
-            } // if nb_anim != 0 {
 
-            // reading sync data
+                    let anim_entry = AnimEntry { action, effect, trans_time, trans_type, extra };
+                    anim_entries.push(anim_entry)
+                }
+                anims.push(Anim {time, anim_entries});
+            } // for j in 0..nb_anim {
+
+            // reading sync data, only Sync#, not InSync 
             for (key, value) in section.iter() {
-                // guard clause, only read Sync#, not InSync 
-                if key.contains("Sync") && !key.contains("InSync") {
-                    let mut sync_times: Vec<usize> = value.split(',').collect::<Vec<&str>>().iter().map(|s| s.parse::<usize>().unwrap()).collect::<Vec<usize>>();
-                    syncs.append(&mut sync_times);
+                if is_numbered(key, "Sync") {
+                    for sync in value.split(',') {
+                        syncs.push(parse_value::<usize>(&eff, key, sync)?);
+                    }
                 }
             }
-            dbg!(&text_count);
-            
-            if syncs.len() > 0 {
             
-                //dbg!(syncs.len());
-                let mut sync_counter = 0;
-                while sync_counter < syncs.len()-1 && text_count != 0 {
-                    for j in 0..text_count {
-                        let key = format!("Text{n}", n = &j);
-                        let value = section.get(key).unwrap_or_default();
-                        if value == "" {
-                            continue;
-                        }
-                        let mut fragments: Vec<(usize, String)> = Vec::new();
-                        let fragments_vec_slashsplit: Vec<String> = value.split(&['/'][..]).collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect();
-                        let mut fragments_vec: Vec<String> = Vec::new();
-                        for fragment in fragments_vec_slashsplit {
-                            for s in fragment.split_inclusive(&[' '][..]).collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect::<Vec<String>>() {
-                                fragments_vec.push(s);
-                            }
-                            
-                        }
-                        //dbg!(&syncs);
-                        let display: String = value.split('/').collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect::<Vec<String>>().join("");
-                        for fragment_string in &fragments_vec {
-                            dbg!(&fragment_string, &sync_counter);
-                            fragments.push((syncs[sync_counter], fragment_string.to_string()));
-                            sync_counter += 1;
-                        }
-                        texts.push(TextEntry {
-                            display,
-                            fragments,
-                            eff_num,
-                        });
-                        
-                        //texts.push(value.to_owned());
+            let mut sync_counter = 0;
+            for j in 0..text_count {
+                let key = format!("Text{n}", n = &j);
+                let value = section.get(key.as_str()).unwrap_or_default();
+
+                // empty lines and lines of layers without syncs have no fragments
+                if value.is_empty() || syncs.is_empty() {
+                    texts.push(TextEntry { display: value.replace('/', ""), fragments: Vec::new(), eff_num });
+                    continue;
+                }
+                let mut fragments: Vec<(usize, String)> = Vec::new();
+                let fragments_vec_slashsplit: Vec<String> = value.split(&['/'][..]).collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect();
+                let mut fragments_vec: Vec<String> = Vec::new();
+                for fragment in fragments_vec_slashsplit {
+                    for s in fragment.split_inclusive(&[' '][..]).collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect::<Vec<String>>() {
+                        fragments_vec.push(s);
                     }
+                    
                 }
-                
-                
+                let display: String = value.split('/').collect::<Vec<&str>>().iter().map(|s| s.to_string()).collect::<Vec<String>>().join("");
+                for fragment_string in &fragments_vec {
+                    // every fragment needs a sync, if they ran out, the line is malformed
+                    let sync = match syncs.get(sync_counter) {
+                        Some(sync) => *sync,
+                        None => return Err(KfnParseError::InvalidIniValue { 
+                            section: eff, key, value: value.to_string() 
+                        }),
+                    };
+                    fragments.push((sync, fragment_string.to_string()));
+                    sync_counter += 1;
+                }
+                texts.push(TextEntry {
+                    display,
+                    fragments,
+                    eff_num,
+                });
             }
             
-            
-            //dbg!(&texts);
             self.effs.push(
                 Eff { 
                     id,
@@ -333,93 +373,97 @@ impl KfnIni {
                     initial_font,
                     initial_active_color,
                     initial_inactive_color,
+                    extra,
                 }
             );
         } // for i in 1..effect_count {
-       
+
+        Ok(())
     }
 
     /// Returns the name of the source sound file. 
-    pub fn get_source_name(&self) -> String {
-        dbg!(self.ini.get_from(Some("General"), "Source").unwrap_or_default()[4..].to_string());
-        self.ini.get_from(Some("General"), "Source").unwrap()[4..].to_string()
+    pub fn get_source_name(&self) -> Result<String, KfnParseError> {
+
+        let source = &self.general.source;
+        if source.is_empty() {
+            return Err(KfnParseError::MissingIniValue { 
+                section: "General".to_string(), key: "Source".to_string() 
+            });
+        }
+
+        // the value starts with the "1,I," prefix
+        match source.get(4..) {
+            Some(name) => Ok(name.to_string()),
+            None => Err(KfnParseError::InvalidIniValue { 
+                section: "General".to_string(), key: "Source".to_string(), value: source.to_string() 
+            }),
+        }
+    }
+
+    /// Recreates the INI from the typed sections. Other sections are kept as they are.
+    pub fn write_ini(&mut self) {
+
+        self.write_section("General", self.general.to_pairs(self.effs.len()));
+
+        let mut materials = vec![("MatCount".to_string(), self.materials.len().to_string())];
+        for (n, material) in self.materials.iter().enumerate() {
+            materials.push((format!("Mat{}", n), material.clone()));
+        }
+        self.write_section("Materials", materials);
+
+        // the section is optional, only written if there is a track or it was already there
+        if !self.mp3_music.tracks.is_empty() || self.ini.section(Some("MP3Music")).is_some() {
+            self.write_section("MP3Music", self.mp3_music.to_pairs());
+        }
+
+        self.set_eff();
+    }
+
+    /// Replaces the keys of the section, keeping its place in the INI.
+    fn write_section(&mut self, name: &str, values: Vec<(String, String)>) {
+
+        let mut properties = Properties::new();
+        for (key, value) in values {
+            properties.insert(key, value);
+        }
+
+        *self.ini.entry(Some(name.to_string())).or_insert(Properties::new()) = properties;
     }
 
     /// Method for setting up the effect in the Ini file.
+    /// Every Eff# section is recreated, the ones left over from removed effects are deleted.
     pub fn set_eff(&mut self) {
 
         // Set the EffectCount - number of Eff sections in the Ini.
-        self.ini.section_mut(Some("General")).unwrap().insert("EffectCount", self.effs.len().to_string());
-        let effect_count = self.ini.get_from(Some("General"), "EffectCount").unwrap_or("0").to_string().parse::<usize>().unwrap();
+        self.ini.with_section(Some("General")).set("EffectCount", self.effs.len().to_string());
 
-        // Iterate through ID of effects
-        for eff_num in 1..=effect_count {
-            
-            // prepare for section header
-            let mut eff_section = String::from("Eff");
+        // Iterate through the effects, the section headers start at 1, the vector at 0!
+        for i in 0..self.effs.len() {
+            let values = eff_values(&self.effs[i]);
+            self.write_section(&format!("Eff{n}", n = i + 1), values);
+        }
 
-            // push number to section header, indexing starts at 1!
-            eff_section.push_str((eff_num).to_string().as_str());
-            
-            let mut section = self.ini.with_section(Some(eff_section.clone()));
-            let eff = &self.effs[eff_num];
-            // get essential fields
-            section
-                .set("ID", &eff.id.to_string())
-                .set("NbAnim", eff.anims.len().to_string())
-                .set("TextCount", eff.texts.len().to_string())
-                .set("Trajectory", eff.initial_trajectory.to_string());
-
-            // iterate through Anim# 
-            for anim_n in 0..self.effs[eff_num].anims.len() {
-
-                // get into the appropriate section
-                let mut section = self.ini.with_section(Some(eff_section.as_str()));
-
-                // clone the Anim#
-                let anim = self.effs[eff_num].anims[anim_n].clone();
-
-                // prepare string for manipulation
-                let mut anim_key = String::from("Anim");
-                // attach row #
-                anim_key.push_str(anim_n.to_string().as_str());
-
-                // prepare value
-                let mut anim_val = String::new();
-
-                // add time, as that is always the first value in line
-                anim_val.push_str(anim.time.to_string().as_str());
-
-                // separator
-                anim_val.push('|');
-
-                // iterate through the entries
-                for anim_entry in anim.anim_entries {
-                    // and push the appropriate value
-                    anim_val.push_str(anim_entry.action.to_string().as_str())
-                }
-                
-                // lastly set it
-                section.set(anim_key, anim_val);
-                
-            }
-        
-            self.ini.with_section(Some(eff_section.clone())).set("Sync0", self.effs[eff_num].syncs.to_owned().iter().map(|n| n.to_string()).collect::<Vec<String>>().join(","));
-            
-            for text_n in 0..self.effs[eff_num].texts.len() {
-                let mut section = self.ini.with_section(Some(eff_section.as_str()));
+        let effect_count = self.effs.len();
+        let left_over: Vec<String> = self.ini.sections()
+            .flatten()
+            .filter(|name| is_numbered(name, "Eff") && name[3..].parse::<usize>().is_ok_and(|n| n == 0 || n > effect_count))
+            .map(|name| name.to_string())
+            .collect();
 
-                let text_value = match self.effs[eff_num].texts[text_n].clone() {
-                    TextEntry { display, fragments: _fragments, eff_num: eff_n } => display
-                };
+        for name in left_over {
+            self.ini.delete(Some(name));
+        }
+    }
 
-                // prepare string for manipulation
-                let mut text_key = String::from("Text");
-                // attach row #
-                text_key.push_str(text_n.to_string().as_str());
+    /// Sets the secondary source, the track with the vocals included.
+    /// If `replaces` is true, it is played instead of the main track, otherwise along with it.
+    pub fn set_secondary_source(&mut self, source: &str, replaces: bool) {
 
-                section.set(text_key, text_value);
-            }
+        let track = Mp3Track::vocal(source, replaces);
+
+        match self.mp3_music.tracks.first_mut() {
+            Some(first) => *first = track,
+            None => self.mp3_music.tracks.push(track),
         }
     }
 
@@ -430,31 +474,86 @@ impl KfnIni {
         
         value.push_str(source);
         
-        self.ini.with_section(Some("General")).set("Source", value);
-    
+        self.general.source = value;
     }
 
     /// Sets the list of files in the ini, based on the entries given.
     pub fn set_materials(&mut self, materials: Vec<Entry>) {
 
-        let mat_count = materials.len()-1;
+        // the Song.ini itself is not listed, wherever it is in the directory
+        self.materials = materials.into_iter()
+            .filter(|entry| entry.filename != "Song.ini")
+            .map(|entry| entry.filename)
+            .collect();
+    }
+}
 
-        self.ini.with_section(Some("Materials")).set("MatCount", mat_count.to_string());
+/// Returns the keys and values of an Eff# section in order.
+fn eff_values(eff: &Eff) -> Vec<(String, String)> {
 
-        for n in 0..mat_count {
+    // starting with the essential fields
+    let mut values: Vec<(String, String)> = vec![
+        ("ID".to_string(), eff.id.to_string()),
+        ("NbAnim".to_string(), eff.anims.len().to_string()),
+        ("TextCount".to_string(), eff.texts.len().to_string()),
+        ("Trajectory".to_string(), eff.initial_trajectory.to_string()),
+    ];
 
-            let mut key = String::from("Mat");
-            
-            key.push_str(n.to_string().as_str());
+    // the initial settings are only written, if there are any
+    if let Some(lib_image) = &eff.initial_lib_image {
+        values.push(("LibImage".to_string(), lib_image.clone()));
+    }
+    if let Some(video_file) = &eff.initial_video_file {
+        values.push(("VideoFile".to_string(), video_file.clone()));
+    }
+    if let Some((font, size)) = &eff.initial_font {
+        values.push(("Font".to_string(), format!("{}*{}", font, size)));
+    }
+    if let Some(color) = &eff.initial_active_color {
+        values.push(("ActiveColor".to_string(), color.clone()));
+    }
+    if let Some(color) = &eff.initial_inactive_color {
+        values.push(("InactiveColor".to_string(), color.clone()));
+    }
 
-            let value = &materials[n].filename;
-            
-            self.ini.with_section(Some("Materials")).set(key.as_str(), value.as_str());
-            
-        }
+    values.extend(eff.extra.iter().cloned());
 
+    // iterate through Anim# 
+    for (anim_n, anim) in eff.anims.iter().enumerate() {
+
+        // add time, as that is always the first value in line
+        let mut anim_val = anim.time.to_string();
+
+        // iterate through the entries
+        for anim_entry in &anim.anim_entries {
+            // separator
+            anim_val.push('|');
+            // and push the appropriate value
+            anim_val.push_str(&anim_entry.to_string());
+        }
+        
+        values.push((format!("Anim{}", anim_n), anim_val));
     }
 
+    // the syncs are split into multiple lines
+    for (sync_n, syncs) in eff.syncs.chunks(SYNCS_PER_LINE).enumerate() {
+        let value = syncs.iter().map(|n| n.to_string()).collect::<Vec<String>>().join(",");
+        values.push((format!("Sync{}", sync_n), value));
+    }
+    
+    for (text_n, text) in eff.texts.iter().enumerate() {
+        values.push((format!("Text{}", text_n), text.to_ini_value()));
+    }
 
+    values
+}
 
-}
\ No newline at end of file
+/// Parses a value of the Song.ini, keeping the section and key for the error.
+fn parse_value<T: std::str::FromStr>(section: &str, key: &str, value: &str) -> Result<T, KfnParseError> {
+    match value.trim().parse::<T>() {
+        Ok(v) => Ok(v),
+        Err(_) => Err(KfnParseError::InvalidIniValue { 
+            section: section.to_string(), key: key.to_string(), value: value.to_string() 
+        }),
+    }
+}
@@ -1,7 +1,9 @@
 use crate::kfn_ini::Trajectory;
+use crate::KfnParseError;
 
 /// Representation of an Eff# headed section, which contains animations, texts, and sync data.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Eff {
     /// The ID of the Eff# layer.
     /// The background layer's ID is always 51.
@@ -20,40 +22,97 @@ pub struct Eff {
     pub initial_active_color: Option<String>,
     /// Initial inactive font color
     pub initial_inactive_color: Option<String>, 
-    /// Collection of the sync timestamps in ms.
+    /// Collection of the sync timestamps in centiseconds.
     pub syncs: Vec<usize>,
     /// Collection of the songtext lines. Separators: '/' ' '
     pub texts: Vec<TextEntry>,
     /// Initial trajectory of the layer.
     pub initial_trajectory: Trajectory,
+    /// Keys of the section not listed above, like InSync, in their order.
+    pub extra: Vec<(String, String)>,
 }
 
 impl Eff {
+    /// Creates an Eff with only the ID and the number of its section set.
+    pub fn new(id: usize, num: usize) -> Self {
+        Self {
+            id,
+            num,
+            anims: Vec::new(),
+            initial_lib_image: None,
+            initial_video_file: None,
+            initial_font: None,
+            initial_active_color: None,
+            initial_inactive_color: None,
+            syncs: Vec::new(),
+            texts: Vec::new(),
+            initial_trajectory: Trajectory::default(),
+            extra: Vec::new(),
+        }
+    }
 
+    /// Adds a line of fragments with their sync times, adding the syncs as well.
+    pub fn add_line(&mut self, fragments: Vec<(usize, String)>) {
+        self.syncs.extend(fragments.iter().map(|(sync, _)| *sync));
+        self.texts.push(TextEntry {
+            display: fragments.iter().map(|(_, fragment)| fragment.as_str()).collect(),
+            fragments,
+            eff_num: self.num,
+        });
+    }
 }
 
 /// Representation of a collection of animations executed at the same time.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Anim {
+    /// The time of the animations in centiseconds.
     pub time: usize,
     pub anim_entries: Vec<AnimEntry>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AnimEntry {
     pub action: Action,
     pub effect: Option<Effect>,
-    pub trans_time: f64,
+    /// The length of the transition, if the entry has one.
+    pub trans_time: Option<f64>,
     pub trans_type: TransType,
+    /// Other parameters of the entry, like layer specific ones, in their order.
+    pub extra: Vec<(String, String)>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TextEntry {
     pub display: String,
     pub fragments: Vec<(usize, String)>,
     pub eff_num: usize,
 }
 
+impl TextEntry {
+    /// Returns the line as it is written in the Song.ini, with the fragments separated by '/'.
+    /// Fragments ending in a space need no separator, as the words are split on spaces anyway.
+    pub fn to_ini_value(&self) -> String {
+
+        if self.fragments.is_empty() {
+            return self.display.clone();
+        }
+
+        let mut value = String::new();
+
+        for (_, fragment) in &self.fragments {
+            if !value.is_empty() && !value.ends_with(' ') {
+                value.push('/');
+            }
+            value.push_str(fragment);
+        }
+
+        value
+    }
+}
+
 impl Into<String> for TextEntry {
     fn into(self) -> String {
         self.display
@@ -62,7 +121,6 @@ impl Into<String> for TextEntry {
 
 impl From<String> for TextEntry {
     fn from(s: String) -> Self {
-        dbg!(&s);
         Self {
             display: s,
             fragments: Vec::new(),
@@ -71,88 +129,207 @@ impl From<String> for TextEntry {
     }
 }
 
-/// Representation of an animation action.
-#[derive(Debug, Clone, Default)]
+/// Representation of an animation action, written as Action:Property=Value.
+/// The known actions have the property named after them, like ChgColActiveColor:ActiveColor=#FFFFFFFF.
+/// Any other action is kept as it is, so it survives a round trip.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Action {
     #[default]
     None,
+    /// Changes the background image.
     ChgBgImg(String),
+    /// Changes the background video.
+    ChgBgVideo(String),
     ChgColColor(String),
+    /// Changes the tint of the background image.
     ChgColImageColor(String),
+    /// Changes the color of the sung fragments.
+    ChgColActiveColor(String),
+    /// Changes the color of the fragments not sung yet.
+    ChgColInactiveColor(String),
+    /// Changes the outline color of the sung fragments.
+    ChgColFrameColor(String),
+    /// Changes the outline color of the fragments not sung yet.
+    ChgColInactiveFrameColor(String),
+    /// Any other color change, with the property and the color.
+    ChgCol(String, String),
     ChgAlphaBlending(String),
     ChgFloatOffsetX(f64),
     ChgFloatOffsetY(f64),
     ChgFloatDepth(f64),
+    ChgFloatZoom(f64),
+    ChgFloatRotation(f64),
+    ChgFloatAlpha(f64),
+    /// Any other change of a number, with the property and the value.
+    ChgFloat(String, f64),
+    /// Changes the font, with the file name and the size.
+    ChgFont(String, u32),
     ChgTrajectory(Trajectory),
+    /// Any other action, with the Action:Property key and the value.
+    Unknown(String, String),
 }
 
-impl From<&str> for Action {
+impl std::str::FromStr for Action {
+    type Err = KfnParseError;
 
-    fn from(s: &str) -> Self {
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
 
-        let colon_split = s.split(':').collect::<Vec<&str>>();
-        let equal_split = s.split('=').collect::<Vec<&str>>();
-
-        let key = colon_split[0];
-        let value = equal_split[1].to_string();
-
-        // else do this
-        match key {
-            "ChgBgImg"          => Action::ChgBgImg(value),
-            "ChgColColor"       => Action::ChgColColor(value),
-            "ChgColImageColor"  => Action::ChgColImageColor(value),
-            "ChgAlphaBlending"  => Action::ChgAlphaBlending(value),
-            "ChgFloatOffsetX"   => Action::ChgFloatOffsetX(value.parse::<f64>().unwrap()),
-            "ChgFloatOffsetY"   => Action::ChgFloatOffsetY(value.parse::<f64>().unwrap()),
-            "ChgFloatDepth"     => Action::ChgFloatDepth(value.parse::<f64>().unwrap()),
-            "ChgTrajectory"     => Action::ChgTrajectory(Trajectory::from(value.as_str())),
-            &_                  => Action::None,
-        }
+        let invalid = || KfnParseError::InvalidAction(s.to_string());
+
+        // the format is Action:Property=Value
+        let (key, value) = s.split_once('=').ok_or_else(invalid)?;
+        let (name, property) = key.split_once(':').ok_or_else(invalid)?;
+        let value = value.to_string();
+
+        let parse_float = |v: &str| v.parse::<f64>().map_err(|_| invalid());
+
+        Ok(match (name, property) {
+            ("ChgBgImg", "LibImage")                            => Action::ChgBgImg(value),
+            ("ChgBgVideo", "VideoFile")                         => Action::ChgBgVideo(value),
+            ("ChgColColor", "Color")                            => Action::ChgColColor(value),
+            ("ChgColImageColor", "ImageColor")                  => Action::ChgColImageColor(value),
+            ("ChgColActiveColor", "ActiveColor")                => Action::ChgColActiveColor(value),
+            ("ChgColInactiveColor", "InactiveColor")            => Action::ChgColInactiveColor(value),
+            ("ChgColFrameColor", "FrameColor")                  => Action::ChgColFrameColor(value),
+            ("ChgColInactiveFrameColor", "InactiveFrameColor")  => Action::ChgColInactiveFrameColor(value),
+            ("ChgAlphaBlending", "AlphaBlending")               => Action::ChgAlphaBlending(value),
+            ("ChgFloatOffsetX", "OffsetX")                      => Action::ChgFloatOffsetX(parse_float(&value)?),
+            ("ChgFloatOffsetY", "OffsetY")                      => Action::ChgFloatOffsetY(parse_float(&value)?),
+            ("ChgFloatDepth", "Depth")                          => Action::ChgFloatDepth(parse_float(&value)?),
+            ("ChgFloatZoom", "Zoom")                            => Action::ChgFloatZoom(parse_float(&value)?),
+            ("ChgFloatRotation", "Rotation")                    => Action::ChgFloatRotation(parse_float(&value)?),
+            ("ChgFloatAlpha", "Alpha")                          => Action::ChgFloatAlpha(parse_float(&value)?),
+            ("ChgTrajectory", "Trajectory")                     => Action::ChgTrajectory(value.parse::<Trajectory>()?),
+            ("ChgFont", "Font") => {
+                let (font, size) = value.split_once('*').ok_or_else(invalid)?;
+                Action::ChgFont(font.to_string(), size.parse::<u32>().map_err(|_| invalid())?)
+            },
+            // the other changes of the same kind are named after their property
+            (name, property) if name.strip_prefix("ChgCol") == Some(property) => {
+                Action::ChgCol(property.to_string(), value)
+            },
+            (name, property) if name.strip_prefix("ChgFloat") == Some(property) => {
+                Action::ChgFloat(property.to_string(), parse_float(&value)?)
+            },
+            _ => Action::Unknown(key.to_string(), value),
+        })
     }
 }
 
-impl ToString for Action {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for Action {
+    /// Writes the action in the Action:Property=Value form.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Action::ChgBgImg(val) => {
-                let mut ret = String::from("ChgBgImg:LibImage=");
-                ret.push_str(val.as_str());
-                ret
-            },
-            _ => " ".to_owned() 
+            Action::None                            => Ok(()),
+            Action::ChgBgImg(val)                   => write!(f, "ChgBgImg:LibImage={}", val),
+            Action::ChgBgVideo(val)                 => write!(f, "ChgBgVideo:VideoFile={}", val),
+            Action::ChgColColor(val)                => write!(f, "ChgColColor:Color={}", val),
+            Action::ChgColImageColor(val)           => write!(f, "ChgColImageColor:ImageColor={}", val),
+            Action::ChgColActiveColor(val)          => write!(f, "ChgColActiveColor:ActiveColor={}", val),
+            Action::ChgColInactiveColor(val)        => write!(f, "ChgColInactiveColor:InactiveColor={}", val),
+            Action::ChgColFrameColor(val)           => write!(f, "ChgColFrameColor:FrameColor={}", val),
+            Action::ChgColInactiveFrameColor(val)   => write!(f, "ChgColInactiveFrameColor:InactiveFrameColor={}", val),
+            Action::ChgCol(property, val)           => write!(f, "ChgCol{0}:{0}={1}", property, val),
+            Action::ChgAlphaBlending(val)           => write!(f, "ChgAlphaBlending:AlphaBlending={}", val),
+            Action::ChgFloatOffsetX(val)            => write!(f, "ChgFloatOffsetX:OffsetX={}", val),
+            Action::ChgFloatOffsetY(val)            => write!(f, "ChgFloatOffsetY:OffsetY={}", val),
+            Action::ChgFloatDepth(val)              => write!(f, "ChgFloatDepth:Depth={}", val),
+            Action::ChgFloatZoom(val)               => write!(f, "ChgFloatZoom:Zoom={}", val),
+            Action::ChgFloatRotation(val)           => write!(f, "ChgFloatRotation:Rotation={}", val),
+            Action::ChgFloatAlpha(val)              => write!(f, "ChgFloatAlpha:Alpha={}", val),
+            Action::ChgFloat(property, val)         => write!(f, "ChgFloat{0}:{0}={1}", property, val),
+            Action::ChgFont(font, size)             => write!(f, "ChgFont:Font={}*{}", font, size),
+            Action::ChgTrajectory(val)              => write!(f, "ChgTrajectory:Trajectory={}", val.to_string()),
+            Action::Unknown(key, val)               => write!(f, "{}={}", key, val),
         }
     }
 }
 
-/// Representation of the available visual effects.
-#[derive(Debug, Clone, Default)]
+impl std::fmt::Display for AnimEntry {
+    /// Writes the entry as it is in an Anim line, the action followed by the effect and the transition.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+
+        write!(f, "{}", self.action)?;
+
+        if let Some(effect) = &self.effect {
+            write!(f, ",Effect={}", effect)?;
+        }
+        if let Some(trans_time) = self.trans_time {
+            write!(f, ",TransitionTime={}", trans_time)?;
+        }
+        if !matches!(self.trans_type, TransType::None) {
+            write!(f, ",TransitionType={}", self.trans_type)?;
+        }
+        for (key, value) in &self.extra {
+            write!(f, ",{}={}", key, value)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Representation of the available visual effects of the transitions.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Effect {
     #[default]
     None,
+    /// Fading from the previous state.
     AlphaBlending,
     MoveRight,
     MoveLeft,
     MoveTop,
     MoveBottom,
-    // TODO the rest of the effects
+    ZoomIn,
+    ZoomOut,
+    RotateLeft,
+    RotateRight,
+    /// Any other effect, by its name.
+    Unknown(String),
 }
 
 impl From<&str> for Effect {
 
     fn from(s: &str) -> Self {
         match s {
+            ""              => Effect::None,
             "AlphaBlending" => Effect::AlphaBlending,
             "MoveRight"     => Effect::MoveRight,
             "MoveLeft"      => Effect::MoveLeft,
             "MoveTop"       => Effect::MoveTop,
             "MoveBottom"    => Effect::MoveBottom,
-            &_              => Effect::None,
+            "ZoomIn"        => Effect::ZoomIn,
+            "ZoomOut"       => Effect::ZoomOut,
+            "RotateLeft"    => Effect::RotateLeft,
+            "RotateRight"   => Effect::RotateRight,
+            &_              => Effect::Unknown(s.to_string()),
         }
     }
 }
 
+impl std::fmt::Display for Effect {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Effect::None            => "",
+            Effect::AlphaBlending   => "AlphaBlending",
+            Effect::MoveRight       => "MoveRight",
+            Effect::MoveLeft        => "MoveLeft",
+            Effect::MoveTop         => "MoveTop",
+            Effect::MoveBottom      => "MoveBottom",
+            Effect::ZoomIn          => "ZoomIn",
+            Effect::ZoomOut         => "ZoomOut",
+            Effect::RotateLeft      => "RotateLeft",
+            Effect::RotateRight     => "RotateRight",
+            Effect::Unknown(name)   => name,
+        };
+        write!(f, "{}", name)
+    }
+}
+
 /// Representation of the various transition types.
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TransType {
     #[default]
     None,
@@ -166,6 +343,8 @@ pub enum TransType {
     Bounce1,
     Bounce3,
     Bounce5,
+    /// Any other transition type, by its name.
+    Unknown(String),
 }
 
 impl From<&str> for TransType {
@@ -182,9 +361,28 @@ impl From<&str> for TransType {
             "Bounce1"           => TransType::Bounce1,
             "Bounce3"           => TransType::Bounce3,
             "Bounce5"           => TransType::Bounce5,
-            &_                  => TransType::None,
+            ""                  => TransType::None,
+            &_                  => TransType::Unknown(s.to_string()),
         }
     }
 }
 
-
+impl std::fmt::Display for TransType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            TransType::None             => "",
+            TransType::Linear           => "Linear",
+            TransType::Smooth           => "Smooth",
+            TransType::Falling          => "Falling",
+            TransType::FallingBouncing  => "FallingBouncing",
+            TransType::Bend1            => "Bend1",
+            TransType::Bend3            => "Bend3",
+            TransType::Bend5            => "Bend5",
+            TransType::Bounce1          => "Bounce1",
+            TransType::Bounce3          => "Bounce3",
+            TransType::Bounce5          => "Bounce5",
+            TransType::Unknown(name)    => name,
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -0,0 +1,234 @@
+use ini::Properties;
+
+use crate::KfnParseError;
+use crate::kfn_ini::parse_value;
+
+/// Representation of the General section, containing the metadata of the song.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct General {
+    pub title: String,
+    pub artist: String,
+    pub album: String,
+    pub composer: String,
+    pub year: String,
+    pub track: String,
+    /// GenreID, -1 if not set.
+    pub genre_id: i32,
+    pub copyright: String,
+    pub comment: String,
+    /// The main track, in the form of "1,I,filename".
+    pub source: String,
+    pub language_id: String,
+    /// Difficulty for men, between 1 and 5.
+    pub diff_men: Option<u32>,
+    /// Difficulty for women, between 1 and 5.
+    pub diff_women: Option<u32>,
+    pub kfn_type: u32,
+    pub properties: String,
+    pub karaoke_version: String,
+    pub vocal_guide: String,
+    pub karafunization: String,
+    /// Keys not listed above, in their order.
+    pub extra: Vec<(String, String)>,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            artist: String::new(),
+            album: String::new(),
+            composer: String::new(),
+            year: String::new(),
+            track: String::new(),
+            genre_id: -1,
+            copyright: String::new(),
+            comment: String::new(),
+            source: String::new(),
+            language_id: String::new(),
+            diff_men: None,
+            diff_women: None,
+            kfn_type: 0,
+            properties: String::new(),
+            karaoke_version: String::new(),
+            vocal_guide: String::new(),
+            karafunization: String::new(),
+            extra: Vec::new(),
+        }
+    }
+}
+
+impl General {
+    /// Reads the section. The EffectCount is not kept, as it is the number of the Eff sections.
+    pub fn from_section(section: &Properties) -> Result<Self, KfnParseError> {
+
+        let mut general = Self::default();
+
+        for (key, value) in section.iter() {
+            match key {
+                "Title"             => general.title = value.to_string(),
+                "Artist"            => general.artist = value.to_string(),
+                "Album"             => general.album = value.to_string(),
+                "Composer"          => general.composer = value.to_string(),
+                "Year"              => general.year = value.to_string(),
+                "Track"             => general.track = value.to_string(),
+                "GenreID"           => general.genre_id = parse_optional("General", key, value)?.unwrap_or(-1),
+                "Copyright"         => general.copyright = value.to_string(),
+                "Comment"           => general.comment = value.to_string(),
+                "Source"            => general.source = value.to_string(),
+                "EffectCount"       => (),
+                "LanguageID"        => general.language_id = value.to_string(),
+                "DiffMen"           => general.diff_men = parse_optional("General", key, value)?,
+                "DiffWomen"         => general.diff_women = parse_optional("General", key, value)?,
+                "KFNType"           => general.kfn_type = parse_optional("General", key, value)?.unwrap_or_default(),
+                "Properties"        => general.properties = value.to_string(),
+                "KaraokeVersion"    => general.karaoke_version = value.to_string(),
+                "VocalGuide"        => general.vocal_guide = value.to_string(),
+                "KaraFunization"    => general.karafunization = value.to_string(),
+                &_                  => general.extra.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        Ok(general)
+    }
+
+    /// Returns the keys and values of the section in order, with the number of Eff sections as EffectCount.
+    pub fn to_pairs(&self, effect_count: usize) -> Vec<(String, String)> {
+
+        let optional = |value: Option<u32>| value.map(|v| v.to_string()).unwrap_or_default();
+
+        let mut pairs: Vec<(String, String)> = [
+            ("Title", self.title.clone()),
+            ("Artist", self.artist.clone()),
+            ("Album", self.album.clone()),
+            ("Composer", self.composer.clone()),
+            ("Year", self.year.clone()),
+            ("Track", self.track.clone()),
+            ("GenreID", self.genre_id.to_string()),
+            ("Copyright", self.copyright.clone()),
+            ("Comment", self.comment.clone()),
+            ("Source", self.source.clone()),
+            ("EffectCount", effect_count.to_string()),
+            ("LanguageID", self.language_id.clone()),
+            ("DiffMen", optional(self.diff_men)),
+            ("DiffWomen", optional(self.diff_women)),
+            ("KFNType", self.kfn_type.to_string()),
+            ("Properties", self.properties.clone()),
+            ("KaraokeVersion", self.karaoke_version.clone()),
+            ("VocalGuide", self.vocal_guide.clone()),
+            ("KaraFunization", self.karafunization.clone()),
+        ].into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+
+        pairs.extend(self.extra.iter().cloned());
+
+        pairs
+    }
+}
+
+/// Representation of the MP3Music section, containing the additional tracks.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mp3Music {
+    pub tracks: Vec<Mp3Track>,
+    /// Keys not listed above, in their order.
+    pub extra: Vec<(String, String)>,
+}
+
+/// An additional track, like the one with the vocals included.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Mp3Track {
+    pub filename: String,
+    /// The comma separated values after the file name.
+    pub options: Vec<String>,
+}
+
+impl Mp3Track {
+    /// Creates a track with the vocals included.
+    /// If `replaces` is true, it is played instead of the main track, otherwise along with it.
+    pub fn vocal(filename: &str, replaces: bool) -> Self {
+        Self { filename: filename.to_string(), options: vec!["0".to_string(), (replaces as u8).to_string()] }
+    }
+
+    /// Returns true, if the track replaces the main track, instead of being played along with it.
+    pub fn replaces_track(&self) -> bool {
+        !matches!(self.options.get(1).map(|option| option.as_str()), Some("0") | None)
+    }
+}
+
+impl From<&str> for Mp3Track {
+    fn from(value: &str) -> Self {
+
+        // the file name may contain commas, so it is cut after the extension, if there is one
+        let end = match value.find(".mp3,") {
+            Some(end) => end + 4,
+            None => value.find(',').unwrap_or(value.len()),
+        };
+
+        let options = match value.get(end + 1..) {
+            Some(options) => options.split(',').map(|option| option.to_string()).collect(),
+            None => Vec::new(),
+        };
+
+        Self { filename: value[..end].to_string(), options }
+    }
+}
+
+impl std::fmt::Display for Mp3Track {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.filename)?;
+        for option in &self.options {
+            write!(f, ",{}", option)?;
+        }
+        Ok(())
+    }
+}
+
+impl Mp3Music {
+    /// Reads the section.
+    pub fn from_section(section: &Properties) -> Self {
+
+        let mut mp3_music = Self::default();
+
+        for (key, value) in section.iter() {
+            match key {
+                "NumTracks" => (),
+                key if is_numbered(key, "Track") => mp3_music.tracks.push(Mp3Track::from(value)),
+                &_ => mp3_music.extra.push((key.to_string(), value.to_string())),
+            }
+        }
+
+        mp3_music
+    }
+
+    /// Returns the keys and values of the section in order.
+    pub fn to_pairs(&self) -> Vec<(String, String)> {
+
+        let mut pairs: Vec<(String, String)> = vec![("NumTracks".to_string(), self.tracks.len().to_string())];
+
+        for (n, track) in self.tracks.iter().enumerate() {
+            pairs.push((format!("Track{}", n), track.to_string()));
+        }
+
+        pairs.extend(self.extra.iter().cloned());
+
+        pairs
+    }
+}
+
+/// Returns true, if the key is the prefix followed by a number, like Track0.
+pub fn is_numbered(key: &str, prefix: &str) -> bool {
+    match key.strip_prefix(prefix) {
+        Some(number) => !number.is_empty() && number.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+/// Parses a value, that may be empty.
+fn parse_optional<T: std::str::FromStr>(section: &str, key: &str, value: &str) -> Result<Option<T>, KfnParseError> {
+    match value.trim() {
+        "" => Ok(None),
+        value => Ok(Some(parse_value(section, key, value)?)),
+    }
+}
@@ -1,6 +1,9 @@
+use crate::KfnParseError;
+
 
 /// Representation of the trajectories the text or image can take.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Trajectory {
     PlainBottomToTop    (f64, f64, f64, f64),
     PlainTopToBottom    (f64, f64, f64, f64),
@@ -13,6 +16,10 @@ pub enum Trajectory {
     MadCircles          (f64, f64, f64, f64),
     BackToFront1        (f64, f64, f64, f64),
     BackToFront2        (f64, f64, f64, f64),
+    /// No trajectory, written as an empty value.
+    None,
+    /// Any other trajectory, kept as it is.
+    Other(String),
 }
 
 impl std::default::Default for Trajectory {
@@ -42,41 +49,36 @@ impl Trajectory {
     }
 }
 
-impl From<&str> for Trajectory {
+impl std::str::FromStr for Trajectory {
+    type Err = KfnParseError;
 
-    fn from(s: &str) -> Self {
-        let key          = s.split('*').collect::<Vec<&str>>()[0];
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         let value   = s.split('*').collect::<Vec<&str>>();
+        let key          = value[0];
 
-        dbg!(&key, &value);
-
-        let mut total_time  : f64 = 1.5;
-        let mut width       : f64 = 1.0;
-        let mut height      : f64 = 1.0;
-        let mut depth       : f64 = 1.0;
+        let trajectory: fn(f64, f64, f64, f64) -> Trajectory = match key {
+            "PlainBottomToTop"      => Trajectory::PlainBottomToTop,
+            "PlainTopToBottom"      => Trajectory::PlainTopToBottom,
+            "BottomLeftToTopRight"  => Trajectory::BottomLeftToTopRight,
+            "BottomRightToTopLeft"  => Trajectory::BottomRightToTopLeft,
+            "TopRightToBottomLeft"  => Trajectory::TopRightToBottomLeft,
+            "TopLeftToBottomRight"  => Trajectory::TopLeftToBottomRight,
+            "Still"                 => Trajectory::Still,
+            "StarWars"              => Trajectory::StarWars,
+            "MadCircles"            => Trajectory::MadCircles,
+            "BackToFront1"          => Trajectory::BackToFront1,
+            "BackToFront2"          => Trajectory::BackToFront2,
+            _ if s.is_empty()       => return Ok(Trajectory::None),
+            &_                      => return Ok(Trajectory::Other(s.to_string())),
+        };
 
-        if !key.is_empty() {
-            total_time  = value[1].parse().unwrap();
-            width       = value[2].parse().unwrap();
-            height      = value[3].parse().unwrap();
-            depth       = value[4].parse().unwrap();
+        // the key is followed by exactly four numbers
+        if value.len() != 5 {
+            return Err(KfnParseError::InvalidTrajectory(s.to_string()));
         }
+        let parse = |v: &str| v.parse::<f64>().map_err(|_| KfnParseError::InvalidTrajectory(s.to_string()));
 
-        dbg!(&value);
-        match key {
-            "PlainBottomToTop"      => Trajectory::PlainBottomToTop     (total_time, width, height, depth),
-            "PlainTopToBottom"      => Trajectory::PlainTopToBottom     (total_time, width, height, depth),
-            "BottomLeftToTopRight"  => Trajectory::BottomLeftToTopRight (total_time, width, height, depth),
-            "BottomRightToTopLeft"  => Trajectory::BottomRightToTopLeft (total_time, width, height, depth),
-            "TopRightToBottomLeft"  => Trajectory::TopRightToBottomLeft (total_time, width, height, depth),
-            "TopLeftToBottomRight"  => Trajectory::TopLeftToBottomRight (total_time, width, height, depth),
-            "Still"                 => Trajectory::Still                (total_time, width, height, depth),
-            "StarWars"              => Trajectory::StarWars             (total_time, width, height, depth),
-            "MadCircles"            => Trajectory::MadCircles           (total_time, width, height, depth),
-            "BackToFront1"          => Trajectory::BackToFront1         (total_time, width, height, depth),
-            "BackToFront2"          => Trajectory::BackToFront2         (total_time, width, height, depth),
-            &_                      => Trajectory::PlainBottomToTop     (total_time, width, height, depth),
-        }
+        Ok(trajectory(parse(value[1])?, parse(value[2])?, parse(value[3])?, parse(value[4])?))
     }
 }
  
@@ -88,6 +90,11 @@ impl ToString for Trajectory {
                 value.push_str(&self.concatenate_values(total_time, width, height, depth));
                 value
             }
+            &Trajectory::PlainTopToBottom(total_time,width, height, depth) => {
+                let mut value = String::from("PlainTopToBottom");
+                value.push_str(&self.concatenate_values(total_time, width, height, depth));
+                value
+            }
             &Trajectory::BottomLeftToTopRight(total_time,width, height, depth) => {
                 let mut value = String::from("BottomLeftToTopRight");
                 value.push_str(&self.concatenate_values(total_time, width, height, depth));
@@ -133,9 +140,8 @@ impl ToString for Trajectory {
                 value.push_str(&self.concatenate_values(total_time, width, height, depth));
                 value
             }
-            &_ => {
-                "PlainBottomToTop*1*1*1*1".to_owned()
-            }           
+            Trajectory::None => String::new(),
+            Trajectory::Other(value) => value.clone(),
         }
     }
 }
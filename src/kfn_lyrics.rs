@@ -0,0 +1,150 @@
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::{Eff, TextEntry};
+
+/// LRC and enhanced LRC lyrics.
+pub mod lrc;
+/// Advanced SubStation Alpha and SubStation Alpha karaoke subtitles.
+pub mod ass;
+/// UltraStar TXT songs.
+pub mod ultrastar;
+/// MIDI karaoke files.
+pub mod kar;
+/// SRT and WebVTT subtitles.
+pub mod subtitles;
+
+/// Layers from this ID on are not text layers, like the background.
+pub const TEXT_LAYER_LIMIT: usize = 51;
+
+/// Color of the sung fragments, if the layer has none, the same as in the player.
+pub const DEFAULT_ACTIVE_COLOR: &str = "#FFFF00FF";
+/// Color of the fragments not sung yet, if the layer has none.
+pub const DEFAULT_INACTIVE_COLOR: &str = "#FFFFFFFF";
+
+/// How long the last fragment of a line lasts at most, in centiseconds, as the syncs only mark the starts.
+pub const LAST_FRAGMENT_DURATION: usize = 100;
+
+/// Returns the text layers of the Song.ini, in their order.
+pub fn text_layers(song: &KfnIni) -> impl Iterator<Item = &Eff> {
+    song.effs.iter().filter(|eff| eff.id < TEXT_LAYER_LIMIT)
+}
+
+/// Returns the synced lines of all text layers with their layer, ordered by their first sync.
+/// Lines without syncs can't be shown at any time, so they are left out.
+pub fn synced_lines(song: &KfnIni) -> Vec<(&Eff, &TextEntry)> {
+
+    let mut lines: Vec<(&Eff, &TextEntry)> = text_layers(song)
+        .flat_map(|eff| eff.texts.iter().map(move |text| (eff, text)))
+        .filter(|(_, text)| !text.fragments.is_empty())
+        .collect();
+
+    // the sort is stable, so lines starting together keep the order of their layers
+    lines.sort_by_key(|(_, text)| text.fragments[0].0);
+
+    lines
+}
+
+/// Returns the fragments of a synced line with their start and end times.
+/// A fragment lasts until the next one starts, the last one until the next line, but at most `LAST_FRAGMENT_DURATION`.
+pub fn timed_fragments(text: &TextEntry, next_line: Option<usize>) -> Vec<(usize, usize, &str)> {
+
+    let mut fragments: Vec<(usize, usize, &str)> = Vec::new();
+
+    for (i, (sync, fragment)) in text.fragments.iter().enumerate() {
+        let end = match text.fragments.get(i + 1) {
+            Some((next, _)) => *next,
+            None => next_line.unwrap_or(usize::MAX).min(sync + LAST_FRAGMENT_DURATION),
+        };
+        fragments.push((*sync, end.max(*sync), fragment));
+    }
+
+    fragments
+}
+
+/// Replaces the text layers of the Song.ini with the given ones, keeping the other layers like the background.
+/// The new layers get the IDs from 1, and the sections are renumbered after the kept ones.
+pub fn set_text_layers(song: &mut KfnIni, layers: Vec<Eff>) {
+
+    song.effs.retain(|eff| eff.id >= TEXT_LAYER_LIMIT);
+
+    for (i, mut eff) in layers.into_iter().enumerate() {
+        eff.id = i + 1;
+        song.effs.push(eff);
+    }
+
+    for (i, eff) in song.effs.iter_mut().enumerate() {
+        eff.num = i + 1;
+        for text in &mut eff.texts {
+            text.eff_num = eff.num;
+        }
+    }
+}
+
+/// Splits the timed pieces of a line into fragments, the way the Song.ini splits them when it is read:
+/// every word is a fragment, and pieces within a word are separate fragments too.
+/// Whitespace is collapsed to single spaces, and '/' is removed, as it is the fragment separator.
+pub fn line_fragments(pieces: &[(usize, String)]) -> Vec<(usize, String)> {
+
+    let mut fragments: Vec<(usize, String)> = Vec::new();
+
+    for (time, piece) in pieces {
+
+        let piece = piece.replace('/', "");
+
+        // a space at the start ends the previous word
+        if piece.starts_with(char::is_whitespace) {
+            if let Some((_, last)) = fragments.last_mut() {
+                if !last.ends_with(' ') {
+                    last.push(' ');
+                }
+            }
+        }
+
+        let words: Vec<&str> = piece.split_whitespace().collect();
+
+        for (i, word) in words.iter().enumerate() {
+            let mut fragment = word.to_string();
+            if i + 1 < words.len() || piece.ends_with(char::is_whitespace) {
+                fragment.push(' ');
+            }
+            fragments.push((*time, fragment));
+        }
+    }
+
+    if let Some((_, last)) = fragments.last_mut() {
+        last.truncate(last.trim_end().len());
+    }
+
+    fragments
+}
+
+/// Parses a Song.ini color in the #RRGGBBAA form into its components.
+pub fn parse_color(color: &str) -> Option<[u8; 4]> {
+
+    let hex = color.trim().strip_prefix('#')?;
+
+    if hex.len() != 8 || !hex.is_ascii() {
+        return None;
+    }
+
+    let mut rgba = [0u8; 4];
+    for (i, component) in rgba.iter_mut().enumerate() {
+        *component = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(rgba)
+}
+
+/// Formats the components of a color in the #RRGGBBAA form of the Song.ini.
+pub fn format_color(rgba: [u8; 4]) -> String {
+    format!("#{:02X}{:02X}{:02X}{:02X}", rgba[0], rgba[1], rgba[2], rgba[3])
+}
+
+/// Returns the font family of a Song.ini font, the file name without the extension of embedded fonts.
+pub fn font_family(font: &str) -> &str {
+    let lowercase = font.to_lowercase();
+    if lowercase.ends_with(".ttf") || lowercase.ends_with(".otf") {
+        &font[..font.len() - 4]
+    } else {
+        font
+    }
+}
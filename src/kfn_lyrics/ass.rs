@@ -0,0 +1,308 @@
+use crate::KfnParseError;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::{Eff, TextEntry};
+use crate::kfn_lyrics::{text_layers, line_fragments, timed_fragments, LAST_FRAGMENT_DURATION, parse_color, format_color, font_family, DEFAULT_ACTIVE_COLOR, DEFAULT_INACTIVE_COLOR};
+
+/// The size of the script, the same as the player window.
+const PLAY_RES: (u32, u32) = (800, 600);
+/// Font of the layers without one.
+const DEFAULT_FONT: (&str, u32) = ("Arial", 40);
+/// Distance of the first layer from the bottom of the screen.
+const MARGIN_V: u32 = 20;
+
+const STYLE_FORMAT: &str = "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, \
+    Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, \
+    MarginL, MarginR, MarginV, Encoding";
+const EVENT_FORMAT: &str = "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text";
+
+/// Formats centiseconds as h:mm:ss.cc, the time format of ASS.
+pub fn format_time(time: usize) -> String {
+    format!("{}:{:02}:{:02}.{:02}", time / 360000, time / 6000 % 60, time / 100 % 60, time % 100)
+}
+
+/// Converts a Song.ini color to the &HAABBGGRR form of ASS, where the alpha is the transparency.
+pub fn to_ass_color(color: &str) -> Option<String> {
+    let [r, g, b, a] = parse_color(color)?;
+    Some(format!("&H{:02X}{:02X}{:02X}{:02X}", 255 - a, b, g, r))
+}
+
+/// Returns the name of the style of a layer.
+pub fn style_name(eff: &Eff) -> String {
+    format!("Eff{}", eff.num)
+}
+
+/// Writes the lyrics of the text layers as ASS karaoke, with a {\k} tag before every fragment.
+/// Every layer has its own style, with the font and the colors of the layer, placed above the previous layer.
+/// The primary color is the active one, the secondary the inactive one, as the fragments turn primary when sung.
+pub fn to_ass(header: &KfnHeader, song: &KfnIni) -> String {
+
+    let mut styles = String::new();
+    let mut events = String::new();
+    let mut margin_v = MARGIN_V;
+
+    for (layer, eff) in text_layers(song).enumerate() {
+
+        let (font, size) = eff.initial_font.as_ref()
+            .map(|(font, size)| (font_family(font), *size))
+            .unwrap_or(DEFAULT_FONT);
+
+        let color = |color: &Option<String>, default: &str| color.as_deref()
+            .and_then(to_ass_color)
+            .or_else(|| to_ass_color(default))
+            .unwrap_or_default();
+
+        styles.push_str(&format!(
+            "Style: {},{},{},{},{},&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,0,2,10,10,{},1\n",
+            style_name(eff), font, size,
+            color(&eff.initial_active_color, DEFAULT_ACTIVE_COLOR),
+            color(&eff.initial_inactive_color, DEFAULT_INACTIVE_COLOR),
+            margin_v,
+        ));
+        margin_v += size * 2;
+
+        let lines: Vec<&TextEntry> = eff.texts.iter().filter(|text| !text.fragments.is_empty()).collect();
+
+        for (i, text) in lines.iter().enumerate() {
+
+            let start = text.fragments[0].0;
+            let last = text.fragments[text.fragments.len() - 1].0;
+            // the line lasts until the next one of the layer starts
+            let end = lines.get(i + 1)
+                .map(|next| next.fragments[0].0)
+                .filter(|next| *next > last)
+                .unwrap_or(last + LAST_FRAGMENT_DURATION);
+
+            let mut karaoke = String::new();
+            for (sync, fragment_end, fragment) in timed_fragments(text, Some(end)) {
+                karaoke.push_str(&format!("{{\\k{}}}{}", fragment_end - sync, fragment));
+            }
+
+            events.push_str(&format!(
+                "Dialogue: {},{},{},{},,0,0,0,karaoke,{}\n",
+                layer, format_time(start), format_time(end), style_name(eff), karaoke,
+            ));
+        }
+    }
+
+    let mut ass = String::from("[Script Info]\n");
+    if !header.title.is_empty() {
+        ass.push_str(&format!("Title: {}\n", header.title));
+    }
+    if !header.artist.is_empty() {
+        ass.push_str(&format!("; Artist: {}\n", header.artist));
+    }
+    ass.push_str(&format!("ScriptType: v4.00+\nPlayResX: {}\nPlayResY: {}\nWrapStyle: 2\n\n", PLAY_RES.0, PLAY_RES.1));
+    ass.push_str(&format!("[V4+ Styles]\n{}\n{}\n", STYLE_FORMAT, styles));
+    ass.push_str(&format!("[Events]\n{}\n{}", EVENT_FORMAT, events));
+
+    ass
+}
+
+/// Parses an ASS time, like h:mm:ss.cc, into centiseconds.
+pub fn parse_time(time: &str) -> Option<usize> {
+
+    let mut parts = time.trim().split(':');
+    let hours = parts.next()?.parse::<usize>().ok()?;
+    let minutes = parts.next()?.parse::<usize>().ok()?;
+    let seconds = parts.next()?;
+    let (seconds, centis) = seconds.split_once('.').unwrap_or((seconds, "0"));
+
+    if parts.next().is_some() || !centis.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    // the fraction is padded or truncated to two digits, like .5 and .500 are both 50 centiseconds
+    let centis = format!("{:0<2}", centis)[..2].parse::<usize>().ok()?;
+
+    Some(((hours * 60 + minutes) * 60 + seconds.parse::<usize>().ok()?) * 100 + centis)
+}
+
+/// Converts an ASS color, like &HAABBGGRR, &HBBGGRR or a decimal number of SSA, to the #RRGGBBAA form of the Song.ini.
+pub fn from_ass_color(color: &str) -> Option<String> {
+
+    let color = color.trim().trim_end_matches('&');
+
+    let value = match color.strip_prefix("&H").or_else(|| color.strip_prefix("&h")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => color.parse::<i64>().ok()? as u32,
+    };
+    let [r, g, b, transparency] = value.to_le_bytes();
+
+    Some(format_color([r, g, b, 255 - transparency]))
+}
+
+/// A style of the script, with the settings used by the layers.
+struct Style {
+    name: String,
+    font: Option<(String, u32)>,
+    active_color: Option<String>,
+    inactive_color: Option<String>,
+}
+
+/// A karaoke event of the script.
+struct Dialogue {
+    style: String,
+    start: usize,
+    /// The pieces of the text with their times.
+    pieces: Vec<(usize, String)>,
+}
+
+/// Splits a line of a section into the fields named by its Format line.
+/// The last field takes the rest of the line, as the text of the events can contain commas.
+fn fields<'a>(format: &'a [String], value: &'a str) -> Option<Vec<(&'a str, &'a str)>> {
+    let values: Vec<&str> = value.splitn(format.len(), ',').collect();
+    if values.len() != format.len() {
+        return None;
+    }
+    Some(format.iter().zip(values).map(|(name, value)| (name.as_str(), value)).collect())
+}
+
+/// Returns the value of a field.
+fn field<'a>(fields: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    fields.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.trim())
+}
+
+/// Splits the text of an event into the pieces timed by the karaoke tags, starting from the time of the event.
+/// The \k, \K, \kf and \ko tags set the duration of the piece after them, in centiseconds.
+/// Other override tags are dropped, the line breaks are replaced with spaces.
+fn karaoke_pieces(text: &str, start: usize) -> Option<Vec<(usize, String)>> {
+
+    let mut pieces: Vec<(usize, String)> = vec![(start, String::new())];
+    let mut time = start;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        match rest.strip_prefix('{') {
+            Some(block) => {
+                let (block, after) = block.split_once('}').unwrap_or((block, ""));
+                rest = after;
+
+                for tag in block.split('\\') {
+                    let duration = tag.strip_prefix("kf")
+                        .or_else(|| tag.strip_prefix("ko"))
+                        .or_else(|| tag.strip_prefix('K'))
+                        .or_else(|| tag.strip_prefix('k'));
+
+                    // the piece starts after the previous ones
+                    if let Some(duration) = duration {
+                        pieces.push((time, String::new()));
+                        time += duration.trim().parse::<f64>().ok()?.max(0.0) as usize;
+                    }
+                }
+            },
+            None => {
+                let end = rest.find('{').unwrap_or(rest.len());
+                let piece = rest[..end].replace("\\N", " ").replace("\\n", " ").replace("\\h", " ");
+                pieces.last_mut()?.1.push_str(&piece);
+                rest = &rest[end..];
+            },
+        }
+    }
+
+    Some(pieces)
+}
+
+/// Reads the karaoke events of an ASS or SSA script into text layers, one layer for every style used.
+/// The primary color of the style is the active color of the layer, the secondary the inactive one.
+/// The title of the script is set in the header.
+pub fn from_ass(ass: &str, header: &mut KfnHeader) -> Result<Vec<Eff>, KfnParseError> {
+
+    let mut section = String::new();
+    let mut format: Vec<String> = Vec::new();
+    let mut styles: Vec<Style> = Vec::new();
+    let mut events: Vec<Dialogue> = Vec::new();
+
+    for (n, line) in ass.lines().enumerate() {
+
+        let invalid = || KfnParseError::InvalidLyrics { line: n + 1, value: line.to_string() };
+        let line = line.trim();
+
+        if line.starts_with('[') && line.ends_with(']') {
+            section = line[1..line.len() - 1].to_lowercase();
+            format.clear();
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) if !line.starts_with(';') => (key.trim(), value.trim_start()),
+            _ => continue,
+        };
+
+        match (section.as_str(), key) {
+            ("script info", "Title") => header.title = value.trim().to_string(),
+            (_, "Format") => format = value.split(',').map(|name| name.trim().to_string()).collect(),
+            ("v4+ styles" | "v4 styles", "Style") => {
+                let fields = fields(&format, value).ok_or_else(invalid)?;
+
+                let font = match (field(&fields, "Fontname"), field(&fields, "Fontsize")) {
+                    (Some(name), Some(size)) if !name.is_empty() => {
+                        Some((name.to_string(), size.parse::<f64>().map_err(|_| invalid())?.round() as u32))
+                    },
+                    _ => None,
+                };
+                let color = |name: &str| match field(&fields, name) {
+                    Some(color) => from_ass_color(color).map(Some).ok_or_else(invalid),
+                    None => Ok(None),
+                };
+
+                styles.push(Style {
+                    name: field(&fields, "Name").unwrap_or_default().to_string(),
+                    font,
+                    active_color: color("PrimaryColour")?,
+                    inactive_color: color("SecondaryColour")?,
+                });
+            },
+            ("events", "Dialogue") => {
+                let fields = fields(&format, value).ok_or_else(invalid)?;
+
+                let start = field(&fields, "Start").and_then(parse_time).ok_or_else(invalid)?;
+                let style = field(&fields, "Style").unwrap_or_default().trim_start_matches('*');
+                // the text is the last field, so it is not trimmed
+                let text = fields.iter().find(|(key, _)| key.eq_ignore_ascii_case("Text")).map(|(_, text)| *text).ok_or_else(invalid)?;
+
+                events.push(Dialogue { 
+                    style: style.to_string(), 
+                    start, 
+                    pieces: karaoke_pieces(text, start).ok_or_else(invalid)?,
+                });
+            },
+            _ => (),
+        }
+    }
+
+    // the layers are in the order of the styles, followed by the styles that are used but not defined
+    let mut names: Vec<&str> = styles.iter().map(|style| style.name.as_str()).collect();
+    for event in &events {
+        if !names.contains(&event.style.as_str()) {
+            names.push(&event.style);
+        }
+    }
+
+    let mut effs: Vec<Eff> = Vec::new();
+
+    for name in names {
+
+        let mut lines: Vec<&Dialogue> = events.iter().filter(|event| event.style == name).collect();
+        if lines.is_empty() {
+            continue;
+        }
+        lines.sort_by_key(|event| event.start);
+
+        let mut eff = Eff::new(effs.len() + 1, effs.len() + 1);
+
+        if let Some(style) = styles.iter().find(|style| style.name == name) {
+            eff.initial_font = style.font.clone();
+            eff.initial_active_color = style.active_color.clone();
+            eff.initial_inactive_color = style.inactive_color.clone();
+        }
+
+        for event in lines {
+            eff.add_line(line_fragments(&event.pieces));
+        }
+
+        effs.push(eff);
+    }
+
+    Ok(effs)
+}
@@ -0,0 +1,146 @@
+use midly::{Smf, Timing, TrackEventKind, MetaMessage};
+
+use crate::KfnParseError;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::eff::Eff;
+use crate::kfn_lyrics::line_fragments;
+
+/// The tempo until the first tempo change, in microseconds per beat.
+const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Converts the ticks of the file to centiseconds, following the tempo changes.
+struct TempoMap {
+    timing: Timing,
+    /// The tempo changes with their tick, in order.
+    changes: Vec<(u64, u32)>,
+}
+
+impl TempoMap {
+    fn time(&self, tick: u64) -> usize {
+        let micros = match self.timing {
+            Timing::Metrical(ticks_per_beat) => {
+                let ticks_per_beat = ticks_per_beat.as_int().max(1) as f64;
+                let mut micros = 0.0;
+                let mut last_tick = 0;
+                let mut tempo = DEFAULT_TEMPO;
+
+                for (change_tick, change_tempo) in &self.changes {
+                    if *change_tick >= tick {
+                        break;
+                    }
+                    micros += (change_tick - last_tick) as f64 * tempo as f64 / ticks_per_beat;
+                    last_tick = *change_tick;
+                    tempo = *change_tempo;
+                }
+
+                micros + (tick - last_tick) as f64 * tempo as f64 / ticks_per_beat
+            },
+            Timing::Timecode(fps, ticks_per_frame) => {
+                tick as f64 * 1_000_000.0 / (fps.as_f32() as f64 * ticks_per_frame.max(1) as f64)
+            },
+        };
+
+        (micros / 10_000.0).round() as usize
+    }
+}
+
+/// Decodes the text of an event. Older files are not UTF-8, those are read as Latin-1.
+fn decode(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|byte| *byte as char).collect(),
+    }
+}
+
+/// Reads the lyrics of a MIDI karaoke (.kar) file into a text layer.
+/// The syllables are the text events, or the lyric events if there are no karaoke text events.
+/// A syllable starting with '/' starts a new line, one starting with '\' a new paragraph, with an empty line before it.
+/// The first @T marker is the title, the second the artist, and @L the language, set in the header.
+pub fn from_kar(data: &[u8], header: &mut KfnHeader) -> Result<Eff, KfnParseError> {
+
+    let smf = Smf::parse(data).map_err(|e| KfnParseError::InvalidMidi(e.to_string()))?;
+
+    let mut changes: Vec<(u64, u32)> = Vec::new();
+    // the syllables of the text and of the lyric events, with their ticks
+    let mut texts: Vec<(u64, String)> = Vec::new();
+    let mut lyrics: Vec<(u64, String)> = Vec::new();
+    let mut titles: Vec<String> = Vec::new();
+    let mut karaoke = false;
+
+    for track in &smf.tracks {
+        let mut tick: u64 = 0;
+
+        for event in track {
+            tick += event.delta.as_int() as u64;
+
+            match event.kind {
+                TrackEventKind::Meta(MetaMessage::Tempo(tempo)) => changes.push((tick, tempo.as_int())),
+                TrackEventKind::Meta(MetaMessage::Text(bytes)) => {
+                    let text = decode(bytes);
+                    match text.strip_prefix('@') {
+                        Some(marker) => {
+                            karaoke = true;
+                            if let Some(value) = marker.strip_prefix('T') {
+                                titles.push(value.trim().to_string());
+                            } else if let Some(value) = marker.strip_prefix('L') {
+                                header.language = value.trim().to_string();
+                            }
+                            // the version, the information and the rest are skipped
+                        },
+                        None => texts.push((tick, text)),
+                    }
+                },
+                TrackEventKind::Meta(MetaMessage::Lyric(bytes)) => lyrics.push((tick, decode(bytes))),
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(title) = titles.first() {
+        header.title = title.clone();
+    }
+    if let Some(artist) = titles.get(1) {
+        header.artist = artist.clone();
+    }
+
+    changes.sort_by_key(|(tick, _)| *tick);
+    let tempo_map = TempoMap { timing: smf.header.timing, changes };
+
+    let mut syllables = if (karaoke && !texts.is_empty()) || lyrics.is_empty() { texts } else { lyrics };
+    // the tracks are read one after the other
+    syllables.sort_by_key(|(tick, _)| *tick);
+
+    let mut eff = Eff::new(1, 1);
+    let mut pieces: Vec<(usize, String)> = Vec::new();
+
+    for (tick, syllable) in syllables {
+
+        // lyric events usually break the lines with a carriage return instead
+        let syllable = syllable.replace(['\r', '\n'], "/");
+        let paragraph = syllable.starts_with('\\');
+        let line_break = paragraph || syllable.starts_with('/');
+
+        if line_break && !pieces.is_empty() {
+            eff.add_line(line_fragments(&pieces));
+            pieces.clear();
+            if paragraph {
+                eff.add_line(Vec::new());
+            }
+        }
+
+        let text = syllable.trim_start_matches(['/', '\\']);
+        pieces.push((tempo_map.time(tick), text.trim_end_matches(['/', '\\']).to_string()));
+
+        // a break at the end of the syllable ends its line
+        if text.ends_with(['/', '\\']) {
+            eff.add_line(line_fragments(&pieces));
+            pieces.clear();
+        }
+    }
+
+    if !pieces.is_empty() {
+        eff.add_line(line_fragments(&pieces));
+    }
+
+    Ok(eff)
+}
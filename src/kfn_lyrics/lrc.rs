@@ -0,0 +1,129 @@
+use crate::KfnParseError;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::Eff;
+use crate::kfn_lyrics::{synced_lines, line_fragments};
+
+/// Formats centiseconds as mm:ss.xx, the time format of LRC.
+pub fn format_time(time: usize) -> String {
+    format!("{:02}:{:02}.{:02}", time / 6000, time / 100 % 60, time % 100)
+}
+
+/// Writes the lyrics of the text layers as LRC, with a timestamp for every line.
+/// The enhanced format adds a <mm:ss.xx> timestamp before every fragment too.
+/// The title, artist and album tags are taken from the header, if they are set.
+pub fn to_lrc(header: &KfnHeader, song: &KfnIni, enhanced: bool) -> String {
+
+    let mut lrc = String::new();
+
+    for (tag, value) in [("ti", &header.title), ("ar", &header.artist), ("al", &header.album)] {
+        if !value.is_empty() {
+            lrc.push_str(&format!("[{}:{}]\n", tag, value));
+        }
+    }
+
+    for (_, text) in synced_lines(song) {
+
+        lrc.push_str(&format!("[{}]", format_time(text.fragments[0].0)));
+
+        if enhanced {
+            for (sync, fragment) in &text.fragments {
+                lrc.push_str(&format!("<{}>{}", format_time(*sync), fragment));
+            }
+        } else {
+            lrc.push_str(&text.display);
+        }
+
+        lrc.push('\n');
+    }
+
+    lrc
+}
+
+/// Parses an LRC time, like mm:ss.xx, mm:ss.xxx or mm:ss, into centiseconds.
+pub fn parse_time(time: &str) -> Option<usize> {
+
+    let (minutes, seconds) = time.trim().split_once(':')?;
+    let (seconds, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+
+    if !fraction.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // the fraction is scaled to two digits
+    let centis = format!("{:0<2}", fraction)[..2].parse::<usize>().ok()?;
+
+    Some((minutes.parse::<usize>().ok()? * 60 + seconds.parse::<usize>().ok()?) * 100 + centis)
+}
+
+/// Reads an LRC or enhanced LRC file into a text layer.
+/// Words with their own <mm:ss.xx> timestamp get that time as their sync, the rest the time of their line.
+/// A line with multiple timestamps is added once for each of them.
+/// The title, artist and album tags are set in the header, the offset tag is applied to the times.
+pub fn from_lrc(lrc: &str, header: &mut KfnHeader) -> Result<Eff, KfnParseError> {
+
+    let mut offset: i64 = 0;
+    // the lines with their time and their timed pieces
+    let mut lines: Vec<(usize, Vec<(usize, String)>)> = Vec::new();
+
+    for (n, line) in lrc.lines().enumerate() {
+
+        let invalid = || KfnParseError::InvalidLyrics { line: n + 1, value: line.to_string() };
+
+        let mut rest = line.trim();
+        let mut times: Vec<usize> = Vec::new();
+
+        while let Some(tag) = rest.strip_prefix('[') {
+            let (tag, after) = tag.split_once(']').ok_or_else(invalid)?;
+            rest = after;
+
+            if let Some(time) = parse_time(tag) {
+                times.push(time);
+                continue;
+            }
+            match tag.split_once(':') {
+                Some(("ti", value)) => header.title = value.trim().to_string(),
+                Some(("ar", value)) => header.artist = value.trim().to_string(),
+                Some(("al", value)) => header.album = value.trim().to_string(),
+                Some(("offset", value)) => offset = value.trim().parse().map_err(|_| invalid())?,
+                // other tags, like the author or the length, have no place in the KFN
+                _ => (),
+            }
+        }
+
+        // lines without a time can't be shown
+        let first = match times.first() {
+            Some(time) => *time,
+            None => continue,
+        };
+
+        // the text before the first word timestamp starts with the line
+        let mut pieces: Vec<(usize, String)> = Vec::new();
+        let mut parts = rest.split('<');
+        pieces.push((first, parts.next().unwrap_or_default().to_string()));
+
+        for part in parts {
+            let (time, text) = part.split_once('>').ok_or_else(invalid)?;
+            pieces.push((parse_time(time).ok_or_else(invalid)?, text.to_string()));
+        }
+
+        for time in &times {
+            // repeated lines keep the distances of their word timestamps
+            let shifted = pieces.iter().map(|(sync, text)| ((sync + time).saturating_sub(first), text.clone())).collect();
+            lines.push((*time, shifted));
+        }
+    }
+
+    // a positive offset shows the lyrics earlier
+    let apply = |time: usize| (time as i64 - offset / 10).max(0) as usize;
+
+    lines.sort_by_key(|(time, _)| *time);
+
+    let mut eff = Eff::new(1, 1);
+
+    for (_, pieces) in lines {
+        let pieces: Vec<(usize, String)> = pieces.into_iter().map(|(time, text)| (apply(time), text)).collect();
+        eff.add_line(line_fragments(&pieces));
+    }
+
+    Ok(eff)
+}
@@ -0,0 +1,94 @@
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::TextEntry;
+use crate::kfn_lyrics::{text_layers, timed_fragments};
+
+/// The shortest time a cue is shown in centiseconds, as players skip empty cues.
+const MIN_CUE_DURATION: usize = 1;
+
+/// Formats centiseconds as hh:mm:ss with the milliseconds after the separator.
+fn format_time(time: usize, separator: char) -> String {
+    format!("{:02}:{:02}:{:02}{}{:03}", time / 360000, time / 6000 % 60, time / 100 % 60, separator, time % 100 * 10)
+}
+
+/// Returns the synced lines of the text layers with their start and end, ordered by their start.
+/// A line lasts until the next line of its layer starts, the last one until its last fragment is sung,
+/// but at least `MIN_CUE_DURATION`, like a line starting together with the next one.
+fn cues(song: &KfnIni) -> Vec<(usize, usize, &TextEntry)> {
+
+    let mut cues: Vec<(usize, usize, &TextEntry)> = Vec::new();
+
+    for eff in text_layers(song) {
+        let lines: Vec<&TextEntry> = eff.texts.iter().filter(|text| !text.fragments.is_empty()).collect();
+
+        for (i, text) in lines.iter().enumerate() {
+            let start = text.fragments[0].0;
+            let end = match lines.get(i + 1) {
+                Some(next) => next.fragments[0].0,
+                None => timed_fragments(text, None).last().map(|(_, end, _)| *end).unwrap_or(start),
+            };
+            cues.push((start, end.max(start + MIN_CUE_DURATION), text));
+        }
+    }
+
+    // the sort is stable, so lines starting together keep the order of their layers
+    cues.sort_by_key(|(start, _, _)| *start);
+
+    cues
+}
+
+/// Escapes the characters with a meaning in the cue text of WebVTT.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Writes the lyrics of the text layers as SRT subtitles, a cue for every line.
+pub fn to_srt(song: &KfnIni) -> String {
+
+    let mut srt = String::new();
+
+    for (n, (start, end, text)) in cues(song).into_iter().enumerate() {
+        srt.push_str(&format!("{}\n{} --> {}\n{}\n\n", n + 1, format_time(start, ','), format_time(end, ','), text.display));
+    }
+
+    srt
+}
+
+/// Writes the lyrics of the text layers as WebVTT subtitles, a cue for every line.
+/// With `word_timestamps`, every fragment after the first has its sync as an inline timestamp.
+/// The title, artist and the rest of the header are written into the header block.
+pub fn to_vtt(header: &KfnHeader, song: &KfnIni, word_timestamps: bool) -> String {
+
+    let mut vtt = String::from("WEBVTT\n");
+
+    let metadata = [
+        ("Title", &header.title), ("Artist", &header.artist), ("Album", &header.album),
+        ("Composer", &header.composer), ("Year", &header.year), ("Language", &header.language),
+    ];
+    for (key, value) in metadata {
+        // the header block ends with the first empty line, and can't contain arrows
+        if !value.is_empty() && !value.contains("-->") {
+            vtt.push_str(&format!("{}: {}\n", key, value.replace('\n', " ")));
+        }
+    }
+    vtt.push('\n');
+
+    for (start, end, text) in cues(song) {
+        vtt.push_str(&format!("{} --> {}\n", format_time(start, '.'), format_time(end, '.')));
+
+        if word_timestamps {
+            for (i, (sync, fragment)) in text.fragments.iter().enumerate() {
+                if i > 0 {
+                    vtt.push_str(&format!("<{}>", format_time(*sync, '.')));
+                }
+                vtt.push_str(&escape(fragment));
+            }
+        } else {
+            vtt.push_str(&escape(&text.display));
+        }
+
+        vtt.push_str("\n\n");
+    }
+
+    vtt
+}
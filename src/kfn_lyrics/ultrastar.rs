@@ -0,0 +1,166 @@
+use crate::KfnParseError;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::{Eff, TextEntry};
+use crate::kfn_lyrics::{text_layers, line_fragments, timed_fragments};
+
+/// The pitch of every exported note, as the KFN has no melody. 0 is the middle C.
+pub const PLACEHOLDER_PITCH: i32 = 0;
+
+/// Returns the length of a beat in milliseconds. UltraStar counts four beats for every beat of the BPM.
+fn beat_length(bpm: f64) -> f64 {
+    15000.0 / bpm
+}
+
+/// Parses a number of the header, which can have a decimal comma.
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().replace(',', ".").parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+/// Splits the next whitespace separated token from the start of the text.
+fn next_token(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    let end = text.find(char::is_whitespace).unwrap_or(text.len());
+    (&text[..end], &text[end..])
+}
+
+/// Reads an UltraStar TXT file into text layers, one for every player of a duet.
+/// The syllables of the notes become the fragments, the beats of the notes their syncs.
+/// The title, artist and year are set in the header.
+pub fn from_ultrastar(txt: &str, header: &mut KfnHeader) -> Result<Vec<Eff>, KfnParseError> {
+
+    let mut bpm: Option<f64> = None;
+    let mut gap: f64 = 0.0;
+    let mut relative = false;
+    // the beat the notes are counted from, moved by the line breaks in relative mode
+    let mut offset: i64 = 0;
+
+    let mut effs: Vec<Eff> = vec![Eff::new(1, 1)];
+    let mut player = 0;
+    let mut pieces: Vec<(usize, String)> = Vec::new();
+
+    for (n, line) in txt.lines().enumerate() {
+
+        let invalid = || KfnParseError::InvalidLyrics { line: n + 1, value: line.to_string() };
+        let line = line.trim_end_matches(['\r', '\n']);
+
+        if let Some(tag) = line.strip_prefix('#') {
+            let (key, value) = tag.split_once(':').ok_or_else(invalid)?;
+            match key.trim().to_uppercase().as_str() {
+                "TITLE" => header.title = value.trim().to_string(),
+                "ARTIST" => header.artist = value.trim().to_string(),
+                "YEAR" => header.year = value.trim().to_string(),
+                "BPM" => bpm = Some(parse_number(value).filter(|bpm| *bpm > 0.0).ok_or_else(invalid)?),
+                "GAP" => gap = parse_number(value).ok_or_else(invalid)?,
+                "RELATIVE" => relative = value.trim().eq_ignore_ascii_case("yes"),
+                // the audio, the video and the rest are not part of the lyrics
+                _ => (),
+            }
+            continue;
+        }
+
+        let kind = match line.chars().next() {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let rest = &line[kind.len_utf8()..];
+
+        match kind {
+            ':' | '*' | 'F' | 'R' | 'G' => {
+                let beat_length = beat_length(bpm.ok_or_else(invalid)?);
+
+                let (start, rest) = next_token(rest);
+                let (_length, rest) = next_token(rest);
+                let (_pitch, rest) = next_token(rest);
+                // the syllable follows a single space, and can start with a space itself
+                let syllable = rest.strip_prefix([' ', '\t']).unwrap_or(rest).replace('~', "");
+
+                let beat = start.parse::<i64>().map_err(|_| invalid())? + offset;
+                let time = (gap + beat as f64 * beat_length) / 10.0;
+
+                pieces.push((time.max(0.0).round() as usize, syllable));
+            },
+            '-' | 'E' | 'P' => {
+                if !pieces.is_empty() {
+                    effs[player].add_line(line_fragments(&pieces));
+                    pieces.clear();
+                }
+
+                match kind {
+                    '-' if relative => {
+                        // the second number is the new start in relative mode, the first if there is only one
+                        let (first, rest) = next_token(rest);
+                        let (second, _) = next_token(rest);
+                        let start = if second.is_empty() { first } else { second };
+                        offset += start.parse::<i64>().map_err(|_| invalid())?;
+                    },
+                    'E' => break,
+                    'P' => {
+                        let number = rest.trim().parse::<usize>().map_err(|_| invalid())?;
+                        player = number.saturating_sub(1);
+                        // every player counts from the start in relative mode
+                        offset = 0;
+                        while effs.len() <= player {
+                            effs.push(Eff::new(effs.len() + 1, effs.len() + 1));
+                        }
+                    },
+                    _ => (),
+                }
+            },
+            _ => return Err(invalid()),
+        }
+    }
+
+    if !pieces.is_empty() {
+        effs[player].add_line(line_fragments(&pieces));
+    }
+
+    effs.retain(|eff| !eff.texts.is_empty());
+
+    Ok(effs)
+}
+
+/// Writes the first text layer with synced lines as UltraStar TXT, at the given BPM.
+/// The gap is the first sync, the beats of the notes are the syncs rounded to the beats of the BPM.
+/// Every note has the same placeholder pitch.
+pub fn to_ultrastar(header: &KfnHeader, song: &KfnIni, bpm: f64) -> String {
+
+    let lines: Vec<&TextEntry> = text_layers(song)
+        .map(|eff| eff.texts.iter().filter(|text| !text.fragments.is_empty()).collect::<Vec<&TextEntry>>())
+        .find(|lines| !lines.is_empty())
+        .unwrap_or_default();
+
+    let gap = lines.first().map(|text| text.fragments[0].0 * 10).unwrap_or(0);
+    let beat_length = beat_length(bpm);
+    let beat = |time: usize| ((time * 10).saturating_sub(gap) as f64 / beat_length).round() as usize;
+
+    let mut txt = String::new();
+
+    // the SORC tag of the header stores the track and type as a "1,I," prefix, so the name comes from the song
+    let source = song.get_source_name().unwrap_or_default();
+    for (tag, value) in [("TITLE", &header.title), ("ARTIST", &header.artist), ("MP3", &source), ("YEAR", &header.year)] {
+        if !value.is_empty() {
+            txt.push_str(&format!("#{}:{}\n", tag, value));
+        }
+    }
+    txt.push_str(&format!("#BPM:{}\n#GAP:{}\n", bpm, gap));
+
+    for (i, text) in lines.iter().enumerate() {
+
+        let next_line = lines.get(i + 1).map(|next| next.fragments[0].0);
+        let mut end = 0;
+
+        for (start, fragment_end, fragment) in timed_fragments(text, next_line) {
+            end = beat(fragment_end).max(beat(start) + 1);
+            txt.push_str(&format!(": {} {} {} {}\n", beat(start), end - beat(start), PLACEHOLDER_PITCH, fragment));
+        }
+
+        if i + 1 < lines.len() {
+            txt.push_str(&format!("- {}\n", end));
+        }
+    }
+
+    txt.push_str("E\n");
+
+    txt
+}
@@ -191,8 +191,9 @@ impl KfnPlayer {
                 event_type: EventType::Background(
                         crate::kfn_ini::eff::AnimEntry {
                             action: Action::ChgBgImg(initial_bg),
-                            effect: None, trans_time: 0.0,
+                            effect: None, trans_time: None,
                             trans_type: crate::kfn_ini::eff::TransType::None,
+                            extra: Vec::new(),
                         }
                     )
             })
@@ -248,7 +249,10 @@ impl KfnPlayer {
             if let Some(font) = &self.data.song.effs[eff_num].initial_font {
                 dbg!(&font.0);
                 //dbg!(&self.text_buffer);
-                current_buffer.font = Font::new(&self.data.get_entry_by_name(&font.0).unwrap().file_bin).unwrap();
+                // keep the default font, if the embedded one is missing or invalid
+                if let Some(Ok(embedded)) = self.data.get_entry_by_name(&font.0).map(|entry| Font::new(&entry.file_bin)) {
+                    current_buffer.font = embedded;
+                }
                 //dbg!(&self.text_buffer[n-1].font);
             }
 
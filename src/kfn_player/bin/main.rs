@@ -15,11 +15,21 @@ fn main() {
     //ffmpeg::init().unwrap();
 
     let args: Vec<String> = env::args().collect();
-    let mut kfn = match args.len() {
-        1 => kfn_rs::Kfn::open("test/input.kfn"),
-        _ => kfn_rs::Kfn::open(args[1].as_str()),
+    let filename = match args.len() {
+        1 => "test/input.kfn",
+        _ => args[1].as_str(),
     };
-    kfn.parse().unwrap();
+    let mut kfn = match kfn_rs::Kfn::open(filename) {
+        Ok(kfn) => kfn,
+        Err(e) => {
+            eprintln!("Could not open {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = kfn.parse() {
+        eprintln!("Could not parse {}: {}", filename, e);
+        std::process::exit(1);
+    }
 
 
     /*let mut ictx = input(&String::from("test/extract/Kagamine Rin & Len - Okochama Sensou.avi")).unwrap();
@@ -72,7 +82,10 @@ fn main() {
 
     //dbg!(kfn.get_animation_events());
 
-    kfn.play_kfn();
+    if let Err(e) = kfn.play_kfn() {
+        eprintln!("Could not play {}: {}", filename, e);
+        std::process::exit(1);
+    }
 }
 
 // fn _save_file(frame: &Video, index: usize) -> std::result::Result<(), std::io::Error> {
@@ -0,0 +1,128 @@
+use rusttype::{Font, Scale, point};
+
+use crate::fonts::DefaultFonts;
+use crate::kfn_data::KfnData;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_ini::eff::{Action, Eff};
+
+/// CD+G graphics for MP3+G.
+pub mod cdg;
+/// Frames of the player, rendered without a window.
+pub mod frame;
+/// Uncompressed video and audio export.
+pub mod video;
+/// Short animated previews.
+pub mod preview;
+/// HTML5 page playing the song in a browser.
+#[cfg(feature = "html")]
+pub mod html;
+/// Printable lyric sheets.
+pub mod pdf;
+
+/// A line of text rasterized into pixels, kept by fragment, so the fragments can be colored separately.
+#[derive(Debug, Clone, Default)]
+pub struct LineImage {
+    pub width: u32,
+    pub height: u32,
+    /// The pixels of every fragment with their coverage from 0 to 1, relative to the top left corner of the line.
+    pub fragments: Vec<Vec<(u32, u32, f32)>>,
+}
+
+/// Returns the images of the background layer with the time they are shown from in centiseconds, ordered by time.
+/// The initial image is shown from the start.
+pub fn background_images(song: &KfnIni) -> Vec<(usize, String)> {
+
+    let mut images: Vec<(usize, String)> = Vec::new();
+
+    if let Some(background) = song.effs.iter().find(|eff| eff.id >= 51) {
+        if let Some(image) = &background.initial_lib_image {
+            images.push((0, image.clone()));
+        }
+        for anim in &background.anims {
+            for entry in &anim.anim_entries {
+                if let Action::ChgBgImg(image) = &entry.action {
+                    images.push((anim.time, image.clone()));
+                }
+            }
+        }
+    }
+
+    // the sort is stable, so the order of the Song.ini is kept for equal times
+    images.sort_by_key(|(time, _)| *time);
+    // changing to the image already shown changes nothing, like the initial image set again at the start
+    images.dedup_by(|next, previous| next.1 == previous.1);
+
+    images
+}
+
+/// Returns the font of a layer: the embedded font file, if it is loaded and valid, otherwise the default font.
+pub fn layer_font(data: &KfnData, eff: &Eff) -> Font<'static> {
+    eff.initial_font.as_ref()
+        .and_then(|(filename, _)| data.get_entry_by_name(filename))
+        .and_then(|entry| Font::try_from_vec(entry.file_bin))
+        .unwrap_or_else(|| Font::try_from_bytes(DefaultFonts::arial()).unwrap())
+}
+
+/// Rasterizes a line of fragments with the given font size in pixels.
+/// Lines wider than `max_width` are scaled down to fit.
+pub fn rasterize_line(font: &Font, fragments: &[&str], size: f32, max_width: u32) -> LineImage {
+
+    let advance = |scale: Scale| {
+        let mut width = 0.0;
+        let mut previous: Option<char> = None;
+        for c in fragments.iter().flat_map(|fragment| fragment.chars()) {
+            if let Some(previous) = previous {
+                width += font.pair_kerning(scale, previous, c);
+            }
+            width += font.glyph(c).scaled(scale).h_metrics().advance_width;
+            previous = Some(c);
+        }
+        width
+    };
+
+    let mut scale = Scale::uniform(size);
+    let width = advance(scale);
+    if width > max_width as f32 {
+        scale = Scale::uniform(size * max_width as f32 / width);
+    }
+
+    let v_metrics = font.v_metrics(scale);
+    let mut image = LineImage {
+        width: advance(scale).ceil().min(max_width as f32) as u32,
+        height: (v_metrics.ascent - v_metrics.descent).ceil() as u32,
+        fragments: Vec::new(),
+    };
+
+    let mut x = 0.0;
+    let mut previous: Option<char> = None;
+
+    for fragment in fragments {
+        let mut pixels: Vec<(u32, u32, f32)> = Vec::new();
+
+        for c in fragment.chars() {
+            if let Some(previous) = previous {
+                x += font.pair_kerning(scale, previous, c);
+            }
+            let glyph = font.glyph(c).scaled(scale);
+            let glyph_advance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(point(x, v_metrics.ascent));
+
+            if let Some(bounds) = glyph.pixel_bounding_box() {
+                glyph.draw(|gx, gy, coverage| {
+                    let px = bounds.min.x + gx as i32;
+                    let py = bounds.min.y + gy as i32;
+                    if px >= 0 && py >= 0 && (px as u32) < image.width && (py as u32) < image.height && coverage > 0.0 {
+                        pixels.push((px as u32, py as u32, coverage));
+                    }
+                });
+            }
+
+            x += glyph_advance;
+            previous = Some(c);
+        }
+
+        image.fragments.push(pixels);
+    }
+
+    image
+}
@@ -0,0 +1,286 @@
+use crate::kfn_data::KfnData;
+use crate::kfn_ini::eff::TextEntry;
+use crate::kfn_lyrics::{text_layers, parse_color, DEFAULT_ACTIVE_COLOR, DEFAULT_INACTIVE_COLOR, LAST_FRAGMENT_DURATION};
+use crate::kfn_render::{layer_font, rasterize_line, LineImage};
+
+/// The size of the screen in pixels.
+pub const WIDTH: usize = 300;
+pub const HEIGHT: usize = 216;
+/// The size of a tile in pixels.
+const TILE_WIDTH: usize = 6;
+const TILE_HEIGHT: usize = 12;
+/// The packets of the stream in a second, three in every centisecond.
+pub const PACKETS_PER_SECOND: usize = 300;
+pub const PACKET_SIZE: usize = 24;
+
+const COMMAND: u8 = 0x09;
+const MEMORY_PRESET: u8 = 1;
+const BORDER_PRESET: u8 = 2;
+const TILE_BLOCK: u8 = 6;
+const TILE_BLOCK_XOR: u8 = 38;
+const LOAD_COLORS_LOW: u8 = 30;
+const LOAD_COLORS_HIGH: u8 = 31;
+
+/// The lines on the screen at once, each in a slot of tile rows, inside the border.
+const SLOTS: usize = 4;
+const SLOT_ROWS: usize = 4;
+/// The font size of the lines in pixels.
+const FONT_SIZE: f32 = 32.0;
+/// How long before its first sync a line is shown, in centiseconds.
+const LEAD_TIME: usize = 300;
+/// How long a line is kept after it is sung, if its slot is not needed sooner.
+const HOLD_TIME: usize = 200;
+/// The palette index of the background. Every layer has two more, the inactive and the active color.
+const BACKGROUND: u8 = 0;
+const MAX_LAYERS: usize = 7;
+
+/// A change of the screen.
+enum Change {
+    /// Shows a line in its slot, in the inactive color.
+    Draw(usize),
+    /// Colors a fragment of a line with the active color.
+    Highlight(usize, usize),
+    /// Clears the slot of a line, if it still shows the line.
+    Clear(usize),
+}
+
+/// A line placed on the screen.
+struct Line {
+    slot: usize,
+    inactive: u8,
+    active: u8,
+    image: LineImage,
+    x: usize,
+    y: usize,
+}
+
+/// A rectangle of pixels.
+type Area = (std::ops::Range<usize>, std::ops::Range<usize>);
+
+/// Builds a packet of the stream. Only the lower 6 bits of the data are used.
+fn packet(instruction: u8, data: &[u8]) -> [u8; PACKET_SIZE] {
+    let mut packet = [0u8; PACKET_SIZE];
+    packet[0] = COMMAND;
+    packet[1] = instruction;
+    for (i, byte) in data.iter().take(16).enumerate() {
+        packet[4 + i] = byte & 0x3F;
+    }
+    packet
+}
+
+/// Builds the packets loading the palette, with 4 bits for every component.
+fn load_colors(palette: &[[u8; 4]; 16]) -> Vec<[u8; PACKET_SIZE]> {
+    [LOAD_COLORS_LOW, LOAD_COLORS_HIGH].iter().enumerate().map(|(half, instruction)| {
+        let mut data = [0u8; 16];
+        for (i, [r, g, b, _]) in palette[half * 8..half * 8 + 8].iter().enumerate() {
+            let (r, g, b) = (r >> 4, g >> 4, b >> 4);
+            data[i * 2] = (r << 2) | (g >> 2);
+            data[i * 2 + 1] = ((g & 0x03) << 4) | b;
+        }
+        packet(*instruction, &data)
+    }).collect()
+}
+
+/// The screen as the player sees it, as palette indices, and the lines shown in the slots.
+struct Screen {
+    pixels: Vec<u8>,
+    slots: [Option<usize>; SLOTS],
+}
+
+impl Screen {
+
+    /// Builds the packets drawing a tile as it is on the screen.
+    /// The two most common colors are drawn first, the rest are added with XOR.
+    fn tile(&self, row: usize, column: usize) -> Vec<[u8; PACKET_SIZE]> {
+
+        let pixel = |x: usize, y: usize| self.pixels[(row * TILE_HEIGHT + y) * WIDTH + column * TILE_WIDTH + x];
+
+        let mut counts = [0usize; 16];
+        for y in 0..TILE_HEIGHT {
+            for x in 0..TILE_WIDTH {
+                counts[pixel(x, y) as usize] += 1;
+            }
+        }
+        let mut colors: Vec<u8> = (0..16).filter(|color| counts[*color as usize] > 0).collect();
+        colors.sort_by_key(|color| std::cmp::Reverse(counts[*color as usize]));
+
+        let first = colors[0];
+        let second = colors.get(1).copied().unwrap_or(first);
+
+        let bits = |matches: &dyn Fn(u8) -> bool| -> Vec<u8> {
+            (0..TILE_HEIGHT).map(|y| {
+                (0..TILE_WIDTH).filter(|x| matches(pixel(*x, y))).map(|x| 0x20u8 >> x).sum()
+            }).collect()
+        };
+
+        let mut data = vec![first, second, row as u8, column as u8];
+        data.extend(bits(&|color| color != first));
+        let mut packets = vec![packet(TILE_BLOCK, &data)];
+
+        for color in colors.iter().skip(2) {
+            let mut data = vec![0, second ^ color, row as u8, column as u8];
+            data.extend(bits(&|pixel| pixel == *color));
+            packets.push(packet(TILE_BLOCK_XOR, &data));
+        }
+
+        packets
+    }
+
+    /// Builds the packets of the tiles covering the area.
+    fn tiles(&self, (x, y): Area) -> Vec<[u8; PACKET_SIZE]> {
+        let mut packets = Vec::new();
+        for row in y.start / TILE_HEIGHT..y.end.div_ceil(TILE_HEIGHT) {
+            for column in x.start / TILE_WIDTH..x.end.div_ceil(TILE_WIDTH) {
+                packets.extend(self.tile(row, column));
+            }
+        }
+        packets
+    }
+
+    /// Fills a slot with the background, and returns its area.
+    fn clear_slot(&mut self, slot: usize) -> Area {
+        let top = (1 + slot * SLOT_ROWS) * TILE_HEIGHT;
+        let rows = top..top + SLOT_ROWS * TILE_HEIGHT;
+        for y in rows.clone() {
+            self.pixels[y * WIDTH + TILE_WIDTH..(y + 1) * WIDTH - TILE_WIDTH].fill(BACKGROUND);
+        }
+        (TILE_WIDTH..WIDTH - TILE_WIDTH, rows)
+    }
+
+    /// Colors the pixels of a fragment, and returns the area changed.
+    fn color(&mut self, line: &Line, fragment: usize, color: u8) -> Option<Area> {
+
+        let mut area: Option<(usize, usize, usize, usize)> = None;
+
+        // there are only a few colors, so the edges of the glyphs are not blended
+        for (x, y, coverage) in &line.image.fragments[fragment] {
+            if *coverage < 0.5 {
+                continue;
+            }
+            let (x, y) = (line.x + *x as usize, line.y + *y as usize);
+            self.pixels[y * WIDTH + x] = color;
+
+            let (min_x, min_y, max_x, max_y) = area.unwrap_or((x, y, x, y));
+            area = Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)));
+        }
+
+        area.map(|(min_x, min_y, max_x, max_y)| (min_x..max_x + 1, min_y..max_y + 1))
+    }
+
+    /// Applies a change, and returns the packets of the tiles changed.
+    fn apply(&mut self, change: &Change, lines: &[Line]) -> Vec<[u8; PACKET_SIZE]> {
+        match *change {
+            Change::Draw(n) => {
+                let line = &lines[n];
+                self.slots[line.slot] = Some(n);
+                let area = self.clear_slot(line.slot);
+                for fragment in 0..line.image.fragments.len() {
+                    self.color(line, fragment, line.inactive);
+                }
+                self.tiles(area)
+            },
+            Change::Highlight(n, fragment) => {
+                let line = &lines[n];
+                if self.slots[line.slot] != Some(n) {
+                    return Vec::new();
+                }
+                match self.color(line, fragment, line.active) {
+                    Some(area) => self.tiles(area),
+                    None => Vec::new(),
+                }
+            },
+            Change::Clear(n) => {
+                let line = &lines[n];
+                if self.slots[line.slot] != Some(n) {
+                    return Vec::new();
+                }
+                self.slots[line.slot] = None;
+                let area = self.clear_slot(line.slot);
+                self.tiles(area)
+            },
+        }
+    }
+}
+
+/// Encodes the lyrics of the text layers as a CD+G graphics stream.
+/// The lines are shown in slots from the top of the screen, some time before they are sung,
+/// in the inactive color of their layer, and every fragment turns to the active color at its sync.
+/// Embedded fonts are used, if they are loaded, otherwise the default font.
+pub fn to_cdg(data: &KfnData) -> Vec<u8> {
+
+    let mut palette = [[0u8, 0, 0, 255]; 16];
+    let mut texts: Vec<(usize, &TextEntry)> = Vec::new();
+    let mut fonts = Vec::new();
+
+    for (layer, eff) in text_layers(&data.song).enumerate() {
+        let index = layer.min(MAX_LAYERS - 1);
+        let color = |color: &Option<String>, default: &str| {
+            color.as_deref().and_then(parse_color).or_else(|| parse_color(default)).unwrap_or_default()
+        };
+        palette[1 + index * 2] = color(&eff.initial_inactive_color, DEFAULT_INACTIVE_COLOR);
+        palette[2 + index * 2] = color(&eff.initial_active_color, DEFAULT_ACTIVE_COLOR);
+
+        fonts.push(layer_font(data, eff));
+        texts.extend(eff.texts.iter().filter(|text| !text.fragments.is_empty()).map(|text| (layer, text)));
+    }
+    texts.sort_by_key(|(_, text)| text.fragments[0].0);
+
+    // placing the lines, and scheduling the changes
+    let mut lines: Vec<Line> = Vec::new();
+    let mut changes: Vec<(usize, Change)> = Vec::new();
+    let mut slot_free = [0usize; SLOTS];
+    let mut last_draw = 0;
+
+    for (n, (layer, text)) in texts.iter().enumerate() {
+
+        let fragments: Vec<&str> = text.fragments.iter().map(|(_, fragment)| fragment.as_str()).collect();
+        let image = rasterize_line(&fonts[*layer], &fragments, FONT_SIZE, (WIDTH - 2 * TILE_WIDTH) as u32);
+        let slot = n % SLOTS;
+        let index = (*layer).min(MAX_LAYERS - 1) as u8;
+
+        lines.push(Line {
+            slot,
+            inactive: 1 + index * 2,
+            active: 2 + index * 2,
+            x: (WIDTH - image.width as usize) / 2,
+            y: (1 + slot * SLOT_ROWS) * TILE_HEIGHT + (SLOT_ROWS * TILE_HEIGHT).saturating_sub(image.height as usize) / 2,
+            image,
+        });
+
+        let start = text.fragments[0].0;
+        let end = text.fragments[text.fragments.len() - 1].0 + LAST_FRAGMENT_DURATION;
+
+        // the line waits for the previous one in its slot, but it is always shown before it is sung
+        let draw = start.saturating_sub(LEAD_TIME).max(slot_free[slot]).max(last_draw).min(start);
+        last_draw = draw;
+        slot_free[slot] = end;
+
+        changes.push((draw, Change::Draw(n)));
+        for (fragment, (sync, _)) in text.fragments.iter().enumerate() {
+            changes.push((*sync, Change::Highlight(n, fragment)));
+        }
+        changes.push((end + HOLD_TIME, Change::Clear(n)));
+    }
+
+    // the sort is stable, so the changes at the same time stay in order
+    changes.sort_by_key(|(time, _)| *time);
+
+    let mut packets: Vec<[u8; PACKET_SIZE]> = vec![
+        packet(MEMORY_PRESET, &[BACKGROUND, 0]),
+        packet(BORDER_PRESET, &[BACKGROUND]),
+    ];
+    packets.extend(load_colors(&palette));
+
+    let mut screen = Screen { pixels: vec![BACKGROUND; WIDTH * HEIGHT], slots: [None; SLOTS] };
+
+    for (time, change) in &changes {
+        // the changes are drawn when the stream gets to their time, or later if it is busy
+        let target = time * PACKETS_PER_SECOND / 100;
+        if packets.len() < target {
+            packets.resize(target, [0u8; PACKET_SIZE]);
+        }
+        packets.extend(screen.apply(change, &lines));
+    }
+
+    packets.concat()
+}
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage, RgbImage};
+use image::imageops::FilterType;
+use rusttype::Font;
+
+use crate::KfnParseError;
+use crate::kfn_data::KfnData;
+use crate::kfn_ini::eff::{Action, Eff, TextEntry};
+use crate::kfn_lyrics::{parse_color, DEFAULT_ACTIVE_COLOR, DEFAULT_INACTIVE_COLOR};
+use crate::kfn_render::{background_images, layer_font, rasterize_line};
+
+/// The font size of the player in its default window height, scaled with the height of the frame.
+const FONT_SIZE: f32 = 70.0;
+const BASE_HEIGHT: f32 = 600.0;
+/// The width of the black outline around the text in the player, in pixels.
+const OUTLINE_WEIGHT: i32 = 5;
+/// The offsets of the outline, drawn around the text.
+const OUTLINE_DIRECTIONS: [(i32, i32); 8] = [(0, 1), (1, -1), (0, -1), (1, 1), (1, 0), (-1, 1), (-1, 0), (-1, -1)];
+
+/// A text layer with its font and colors.
+struct Layer<'a> {
+    eff: &'a Eff,
+    font: Font<'static>,
+    inactive: [u8; 4],
+    active: [u8; 4],
+}
+
+/// Renders the frames of the player without a window, on the CPU.
+/// Every frame is composed of the background image with its tint, and the current line of every text layer,
+/// with the sung fragments in the active color, like in the player.
+/// Times are in milliseconds, unlike the centiseconds of the Song.ini, so frames can fall between two syncs.
+pub struct FrameRenderer<'a> {
+    data: &'a KfnData,
+    pub width: u32,
+    pub height: u32,
+    layers: Vec<Layer<'a>>,
+    /// The background images and the tints in the order of their time, in centiseconds.
+    backgrounds: Vec<(usize, String)>,
+    tints: Vec<(usize, [u8; 4])>,
+    /// The decoded background images, resized to the frame. Invalid images are None.
+    images: HashMap<String, Option<RgbImage>>,
+}
+
+impl<'a> FrameRenderer<'a> {
+    /// Creates a renderer for frames of the given size.
+    /// The background images and the fonts of the layers have to be loaded. The width and the height can't be zero.
+    pub fn new(data: &'a KfnData, width: u32, height: u32) -> Result<Self, KfnParseError> {
+
+        if width == 0 || height == 0 {
+            return Err(KfnParseError::InvalidFrameSize { width, height });
+        }
+
+        let mut tints: Vec<(usize, [u8; 4])> = Vec::new();
+
+        if let Some(background) = data.song.effs.iter().find(|eff| eff.id >= 51) {
+            for anim in &background.anims {
+                for entry in &anim.anim_entries {
+                    if let Action::ChgColImageColor(color) = &entry.action {
+                        if let Some(rgba) = parse_color(color) {
+                            tints.push((anim.time, rgba));
+                        }
+                    }
+                }
+            }
+        }
+        tints.sort_by_key(|(time, _)| *time);
+
+        let layers = data.song.effs.iter()
+            .filter(|eff| eff.id < 51)
+            .map(|eff| Layer {
+                eff,
+                font: layer_font(data, eff),
+                inactive: eff.initial_inactive_color.as_deref().and_then(parse_color)
+                    .unwrap_or_else(|| parse_color(DEFAULT_INACTIVE_COLOR).unwrap()),
+                active: eff.initial_active_color.as_deref().and_then(parse_color)
+                    .unwrap_or_else(|| parse_color(DEFAULT_ACTIVE_COLOR).unwrap()),
+            })
+            .collect();
+
+        Ok(Self { data, width, height, layers, backgrounds: background_images(&data.song), tints, images: HashMap::new() })
+    }
+
+    /// Renders the frame at the given time in milliseconds.
+    pub fn render(&mut self, time_ms: usize) -> RgbaImage {
+
+        let mut frame = RgbaImage::from_pixel(self.width, self.height, Rgba([0, 0, 0, 255]));
+
+        let centiseconds = time_ms / 10;
+        let background = self.backgrounds.iter().rev().find(|(t, _)| *t <= centiseconds).map(|(_, name)| name.clone());
+
+        if let Some(name) = background {
+            let tint = self.tints.iter().rev()
+                .find(|(t, _)| *t <= centiseconds)
+                .map(|(_, tint)| *tint)
+                .unwrap_or([255, 255, 255, 255]);
+            if let Some(image) = self.background(&name) {
+                draw_background(&mut frame, image, tint);
+            }
+        }
+
+        let size = FONT_SIZE * self.height as f32 / BASE_HEIGHT;
+        let lines: Vec<(&Layer, &TextEntry)> = self.layers.iter()
+            .filter_map(|layer| current_line(layer.eff, centiseconds).map(|line| (layer, line)))
+            .collect();
+
+        let images: Vec<_> = lines.iter()
+            .map(|(layer, line)| {
+                let fragments: Vec<&str> = line.fragments.iter().map(|(_, fragment)| fragment.as_str()).collect();
+                rasterize_line(&layer.font, &fragments, size, self.width)
+            })
+            .collect();
+
+        // the lines are centered, and stacked in the order of the layers
+        let mut y = (self.height as i32 - images.iter().map(|image| image.height as i32).sum::<i32>()) / 2;
+
+        for ((layer, line), image) in lines.iter().zip(&images) {
+            let x = (self.width as i32 - image.width as i32) / 2;
+
+            for n in 1..OUTLINE_WEIGHT {
+                let alpha = 1.0 - 2.0 / n as f32;
+                if alpha <= 0.0 {
+                    continue;
+                }
+                for (dx, dy) in OUTLINE_DIRECTIONS {
+                    for fragment in &image.fragments {
+                        for (px, py, coverage) in fragment {
+                            blend(&mut frame, x + *px as i32 + dx * n, y + *py as i32 + dy * n, [0, 0, 0, 255], coverage * alpha);
+                        }
+                    }
+                }
+            }
+
+            for (fragment, (sync, _)) in image.fragments.iter().zip(&line.fragments) {
+                let color = if *sync <= centiseconds { layer.active } else { layer.inactive };
+                for (px, py, coverage) in fragment {
+                    blend(&mut frame, x + *px as i32, y + *py as i32, color, *coverage);
+                }
+            }
+
+            y += image.height as i32;
+        }
+
+        frame
+    }
+
+    /// Returns the frames from the start until the end time in milliseconds at the given frame rate.
+    pub fn frames(&mut self, start_ms: usize, end_ms: usize, fps: u32) -> Frames<'_, 'a> {
+        Frames { renderer: self, start_ms, end_ms, fps, frame: 0 }
+    }
+
+    /// Returns the background image resized to fill the frame, decoding it on first use.
+    fn background(&mut self, name: &str) -> Option<&RgbImage> {
+        let (data, width, height) = (self.data, self.width, self.height);
+        self.images.entry(name.to_string())
+            .or_insert_with(|| {
+                let entry = data.get_entry_by_name(name)?;
+                let image = image::load_from_memory(&entry.file_bin).ok()?;
+                Some(image.resize_to_fill(width, height, FilterType::Triangle).into_rgb8())
+            })
+            .as_ref()
+    }
+}
+
+/// Iterator over the frames of a time range, created by [`FrameRenderer::frames`].
+pub struct Frames<'r, 'a> {
+    renderer: &'r mut FrameRenderer<'a>,
+    start_ms: usize,
+    end_ms: usize,
+    fps: u32,
+    frame: usize,
+}
+
+impl Frames<'_, '_> {
+    /// The time of the next frame in milliseconds.
+    fn time(&self) -> usize {
+        self.start_ms + self.frame * 1000 / self.fps.max(1) as usize
+    }
+}
+
+impl Iterator for Frames<'_, '_> {
+    type Item = RgbaImage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let time = self.time();
+        if time >= self.end_ms {
+            return None;
+        }
+        self.frame += 1;
+        Some(self.renderer.render(time))
+    }
+}
+
+/// Returns the line of the layer shown at the given time in centiseconds: the last one that has started,
+/// or the first one before the lyrics start, as the player keeps the line until the next one.
+fn current_line(eff: &Eff, time: usize) -> Option<&TextEntry> {
+    let mut lines = eff.texts.iter().filter(|text| !text.fragments.is_empty());
+    let first = lines.clone().next();
+    lines.rfind(|text| text.fragments[0].0 <= time).or(first)
+}
+
+/// Draws the background over the black frame, multiplied by the tint, like a tinted image in the player.
+fn draw_background(frame: &mut RgbaImage, image: &RgbImage, tint: [u8; 4]) {
+    for (pixel, source) in frame.pixels_mut().zip(image.pixels()) {
+        for c in 0..3 {
+            let value = source[c] as u32 * tint[c] as u32 * tint[3] as u32 / (255 * 255);
+            pixel[c] = value as u8;
+        }
+    }
+}
+
+/// Blends a color over a pixel of the frame with the given coverage. Pixels outside the frame are skipped.
+fn blend(frame: &mut RgbaImage, x: i32, y: i32, color: [u8; 4], coverage: f32) {
+    if x < 0 || y < 0 || x as u32 >= frame.width() || y as u32 >= frame.height() {
+        return;
+    }
+    let alpha = coverage.clamp(0.0, 1.0) * color[3] as f32 / 255.0;
+    let pixel = frame.get_pixel_mut(x as u32, y as u32);
+    for c in 0..3 {
+        pixel[c] = (color[c] as f32 * alpha + pixel[c] as f32 * (1.0 - alpha)).round() as u8;
+    }
+}
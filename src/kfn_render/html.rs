@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+
+use crate::KfnParseError;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_lyrics::{text_layers, parse_color, format_color, DEFAULT_ACTIVE_COLOR, DEFAULT_INACTIVE_COLOR};
+use crate::kfn_render::background_images;
+
+/// The script of the page. It shows the current line of every layer, colors the sung fragments,
+/// and changes the background, following the time of the audio.
+const SCRIPT: &str = r#"
+const audio = document.getElementById("audio");
+const lyrics = document.getElementById("lyrics");
+
+// the last event that has started, or -1
+const current = (times, time) => times.reduce((found, start, i) => start <= time ? i : found, -1);
+
+const views = layers.map(layer => {
+    const element = document.createElement("div");
+    element.className = "line";
+    if (layer.font) element.style.fontFamily = layer.font;
+    lyrics.appendChild(element);
+    return { layer, element, line: -1 };
+});
+
+let background = -1;
+
+function update() {
+    const time = audio.currentTime;
+
+    const image = current(backgrounds.map(b => b[0]), time);
+    if (image !== background) {
+        background = image;
+        document.body.style.backgroundImage = image < 0 ? "none" : `url("${backgrounds[image][1]}")`;
+    }
+
+    for (const view of views) {
+        // before the lyrics start, the first line is shown
+        const line = Math.max(current(view.layer.lines.map(l => l[0][0]), time), 0);
+        if (line !== view.line && view.layer.lines.length > 0) {
+            view.line = line;
+            view.element.replaceChildren(...view.layer.lines[line].map(([start, text]) => {
+                const span = document.createElement("span");
+                span.textContent = text;
+                span.dataset.start = start;
+                return span;
+            }));
+        }
+        for (const span of view.element.children) {
+            span.style.color = span.dataset.start <= time ? view.layer.active : view.layer.inactive;
+        }
+    }
+
+    requestAnimationFrame(update);
+}
+
+requestAnimationFrame(update);
+"#;
+
+/// Returns the names of the entries used by the page: the main audio, the background images and the fonts of the layers.
+pub fn assets(song: &KfnIni) -> Result<Vec<String>, KfnParseError> {
+
+    let mut assets = vec![song.get_source_name()?];
+
+    let images = background_images(song).into_iter().map(|(_, filename)| filename);
+    let fonts = text_layers(song).filter_map(|eff| eff.initial_font.as_ref().map(|(filename, _)| filename.clone()));
+
+    for filename in images.chain(fonts) {
+        if !assets.contains(&filename) {
+            assets.push(filename);
+        }
+    }
+
+    Ok(assets)
+}
+
+/// Returns the file as a data URL, with the media type guessed from the extension.
+pub fn data_url(filename: &str, data: &[u8]) -> String {
+    format!("data:{};base64,{}", media_type(filename), STANDARD.encode(data))
+}
+
+/// Returns the media type of a file from its extension.
+fn media_type(filename: &str) -> &'static str {
+    let extension = filename.rsplit('.').next().unwrap_or_default().to_ascii_lowercase();
+    match extension.as_str() {
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "jpg" | "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "webp" => "image/webp",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Returns the path of a file relative to the page, with the characters not allowed in a URL percent-encoded.
+pub fn relative_url(directory: &str, filename: &str) -> String {
+    format!("{}/{}", directory, filename).bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Escapes the characters with a meaning in HTML text and attributes.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Returns the text as a string literal of the script. Closing tags can't end the script early.
+fn js_string(text: &str) -> String {
+    let mut literal = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => literal.push_str("\\\""),
+            '\\' => literal.push_str("\\\\"),
+            '<' => literal.push_str("\\u003C"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\u{2028}' | '\u{2029}' => literal.push_str(&format!("\\u{:04X}", c as u32)),
+            c => literal.push(c),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Formats centiseconds as seconds for the script.
+fn seconds(time: usize) -> String {
+    format!("{}.{:02}", time / 100, time % 100)
+}
+
+/// Writes an HTML5 page playing the song, with the lyrics highlighted in sync with the audio.
+/// The audio, the background images and the fonts are referenced by the URLs of their entries, like data URLs
+/// for a self-contained page. Entries without a URL are left out.
+pub fn to_html(header: &KfnHeader, song: &KfnIni, urls: &HashMap<String, String>) -> Result<String, KfnParseError> {
+
+    let source = song.get_source_name()?;
+
+    let mut fonts = String::new();
+    let mut layers: Vec<String> = Vec::new();
+
+    for (n, eff) in text_layers(song).enumerate() {
+
+        let font = eff.initial_font.as_ref().and_then(|(filename, _)| urls.get(filename));
+        if let Some(url) = font {
+            fonts.push_str(&format!("@font-face {{ font-family: \"layer{}\"; src: url(\"{}\"); }}\n", n, url));
+        }
+
+        let color = |color: &Option<String>, default: &str| {
+            format_color(color.as_deref().and_then(parse_color).or_else(|| parse_color(default)).unwrap_or_default())
+        };
+
+        let lines: Vec<String> = eff.texts.iter()
+            .filter(|text| !text.fragments.is_empty())
+            .map(|text| {
+                let fragments: Vec<String> = text.fragments.iter()
+                    .map(|(time, fragment)| format!("[{}, {}]", seconds(*time), js_string(fragment)))
+                    .collect();
+                format!("[{}]", fragments.join(", "))
+            })
+            .collect();
+
+        layers.push(format!(
+            "{{ font: {}, active: {}, inactive: {}, lines: [\n{}\n] }}",
+            font.map(|_| js_string(&format!("layer{}", n))).unwrap_or("null".to_string()),
+            js_string(&color(&eff.initial_active_color, DEFAULT_ACTIVE_COLOR)),
+            js_string(&color(&eff.initial_inactive_color, DEFAULT_INACTIVE_COLOR)),
+            lines.join(",\n"),
+        ));
+    }
+
+    let backgrounds: Vec<String> = background_images(song).into_iter()
+        .filter_map(|(time, filename)| urls.get(&filename).map(|url| format!("[{}, {}]", seconds(time), js_string(url))))
+        .collect();
+
+    let title = match header.artist.is_empty() {
+        true => escape(&header.title),
+        false => format!("{} - {}", escape(&header.artist), escape(&header.title)),
+    };
+    let language = match header.language.is_empty() {
+        true => "en".to_string(),
+        false => escape(&header.language.to_lowercase()),
+    };
+
+    let mut html = String::new();
+
+    html.push_str(&format!("<!DOCTYPE html>\n<html lang=\"{}\">\n<head>\n<meta charset=\"utf-8\">\n", language));
+    html.push_str("<meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n");
+    html.push_str(&format!("<title>{}</title>\n", title));
+    html.push_str("<style>\n");
+    html.push_str(&fonts);
+    html.push_str("body { margin: 0; height: 100vh; display: flex; flex-direction: column; background: #000 center / cover no-repeat; \
+        color: #fff; font-family: sans-serif; }\n");
+    html.push_str("h1 { margin: 0; padding: 0.5em; font-size: 1em; text-shadow: 0 0 3px #000; }\n");
+    html.push_str("#lyrics { flex: 1; display: flex; flex-direction: column; justify-content: center; align-items: center; }\n");
+    html.push_str(".line { min-height: 1.2em; font-size: min(8vh, 5vw); font-weight: bold; white-space: pre; \
+        text-shadow: -2px -2px 0 #000, 2px -2px 0 #000, -2px 2px 0 #000, 2px 2px 0 #000; }\n");
+    html.push_str("audio { width: 100%; }\n");
+    html.push_str("</style>\n</head>\n<body>\n");
+    html.push_str(&format!("<h1>{}</h1>\n", title));
+    html.push_str("<div id=\"lyrics\"></div>\n");
+    html.push_str(&format!("<audio id=\"audio\" controls src=\"{}\"></audio>\n", escape(urls.get(&source).map(|url| url.as_str()).unwrap_or_default())));
+    html.push_str("<script>\n");
+    html.push_str(&format!("const backgrounds = [{}];\n", backgrounds.join(", ")));
+    html.push_str(&format!("const layers = [\n{}\n];\n", layers.join(",\n")));
+    html.push_str(SCRIPT);
+    html.push_str("</script>\n</body>\n</html>\n");
+
+    Ok(html)
+}
@@ -0,0 +1,181 @@
+use std::collections::HashMap;
+
+use printpdf::{Color, IndirectFontRef, Mm, PdfDocument, PdfDocumentReference, PdfLayerReference, Rgb};
+use rusttype::{Font, Scale};
+
+use crate::KfnParseError;
+use crate::fonts::DefaultFonts;
+use crate::kfn_data::KfnData;
+use crate::kfn_header::KfnHeader;
+use crate::kfn_lyrics::text_layers;
+
+/// The size of an A4 page and its margins in millimeters.
+const PAGE_WIDTH: f32 = 210.0;
+const PAGE_HEIGHT: f32 = 297.0;
+const MARGIN: f32 = 20.0;
+/// The width of the timestamps in the left margin.
+const TIMESTAMP_WIDTH: f32 = 16.0;
+
+/// Font sizes in points.
+const TITLE_SIZE: f32 = 20.0;
+const INFO_SIZE: f32 = 11.0;
+const HEADING_SIZE: f32 = 13.0;
+const TEXT_SIZE: f32 = 11.0;
+const TIMESTAMP_SIZE: f32 = 8.0;
+/// The height of a line relative to its font size.
+const LINE_HEIGHT: f32 = 1.4;
+
+/// A font embedded into the document, and the same font for measuring the text.
+struct PdfFont {
+    reference: IndirectFontRef,
+    metrics: Font<'static>,
+}
+
+/// The document being written, with the position of the next line on the current page.
+struct Sheet {
+    document: PdfDocumentReference,
+    layer: PdfLayerReference,
+    y: f32,
+}
+
+impl Sheet {
+    /// Moves down by a line of the given font size, starting a new page if it doesn't fit.
+    fn next_line(&mut self, size: f32) {
+        let height = points_to_mm(size * LINE_HEIGHT);
+        if self.y - height < MARGIN {
+            let (page, layer) = self.document.add_page(Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Lyrics");
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.y = PAGE_HEIGHT - MARGIN;
+        }
+        self.y -= height;
+    }
+
+    /// Writes text at the current line, from the given distance from the left edge.
+    fn write(&self, text: &str, size: f32, x: f32, font: &PdfFont) {
+        self.layer.use_text(text, size, Mm(x), Mm(self.y), &font.reference);
+    }
+}
+
+fn points_to_mm(points: f32) -> f32 {
+    points * 25.4 / 72.0
+}
+
+/// Returns the width of the text in millimeters.
+fn text_width(font: &Font, text: &str, size: f32) -> f32 {
+
+    let scale = Scale::uniform(size);
+    let mut width = 0.0;
+    let mut previous: Option<char> = None;
+
+    for c in text.chars() {
+        if let Some(previous) = previous {
+            width += font.pair_kerning(scale, previous, c);
+        }
+        width += font.glyph(c).scaled(scale).h_metrics().advance_width;
+        previous = Some(c);
+    }
+
+    points_to_mm(width)
+}
+
+/// Splits the text into lines not wider than the given width, at the spaces. Words too long for a line are kept whole.
+fn wrap(font: &Font, text: &str, size: f32, max_width: f32) -> Vec<String> {
+
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+
+    for word in text.split_inclusive(' ') {
+        if !line.is_empty() && text_width(font, (line.clone() + word).trim_end(), size) > max_width {
+            lines.push(line.trim_end().to_string());
+            line.clear();
+        }
+        line.push_str(word);
+    }
+    lines.push(line.trim_end().to_string());
+
+    lines
+}
+
+/// Formats centiseconds as m:ss for the margin.
+fn format_time(time: usize) -> String {
+    format!("{}:{:02}", time / 6000, time / 100 % 60)
+}
+
+/// Embeds a font into the document. Returns None, if it can't be used.
+fn embed_font(document: &PdfDocumentReference, data: Vec<u8>) -> Option<PdfFont> {
+    let reference = document.add_external_font(std::io::Cursor::new(data.clone())).ok()?;
+    let metrics = Font::try_from_vec(data)?;
+    Some(PdfFont { reference, metrics })
+}
+
+/// Writes the lyrics of the text layers as a printable PDF lyric sheet on A4 pages.
+/// The title, the artist, the album and the composer are at the top, followed by the lines of every layer.
+/// Every layer is written with its embedded font, if it is loaded and valid, otherwise with the default font.
+/// With `timestamps`, the time of every synced line is written in the margin.
+pub fn to_pdf(header: &KfnHeader, data: &KfnData, timestamps: bool) -> Result<Vec<u8>, KfnParseError> {
+
+    let title = match header.title.is_empty() {
+        true => "Lyrics",
+        false => header.title.as_str(),
+    };
+
+    let (document, page, layer) = PdfDocument::new(title, Mm(PAGE_WIDTH), Mm(PAGE_HEIGHT), "Lyrics");
+    let layer = document.get_page(page).get_layer(layer);
+
+    let default_font = embed_font(&document, DefaultFonts::arial().to_vec())
+        .ok_or(KfnParseError::Pdf("the default font can't be embedded".to_string()))?;
+
+    let mut sheet = Sheet { document, layer, y: PAGE_HEIGHT - MARGIN };
+
+    // the header
+    sheet.next_line(TITLE_SIZE);
+    sheet.write(title, TITLE_SIZE, MARGIN, &default_font);
+
+    let info = [("Artist", &header.artist), ("Album", &header.album), ("Composer", &header.composer)];
+    for (name, value) in info.iter().filter(|(_, value)| !value.is_empty()) {
+        sheet.next_line(INFO_SIZE);
+        sheet.write(&format!("{}: {}", name, value), INFO_SIZE, MARGIN, &default_font);
+    }
+
+    let text_x = match timestamps {
+        true => MARGIN + TIMESTAMP_WIDTH,
+        false => MARGIN,
+    };
+    let text_width_max = PAGE_WIDTH - MARGIN - text_x;
+
+    // the fonts of the layers, embedded only once
+    let mut fonts: HashMap<String, Option<PdfFont>> = HashMap::new();
+
+    for (n, eff) in text_layers(&data.song).enumerate() {
+
+        let font = eff.initial_font.as_ref()
+            .and_then(|(filename, _)| {
+                fonts.entry(filename.clone())
+                    .or_insert_with(|| data.get_entry_by_name(filename).and_then(|entry| embed_font(&sheet.document, entry.file_bin)))
+                    .as_ref()
+            })
+            .unwrap_or(&default_font);
+
+        // an empty line before every layer
+        sheet.next_line(HEADING_SIZE);
+        sheet.next_line(HEADING_SIZE);
+        sheet.write(&format!("Layer {}", n + 1), HEADING_SIZE, MARGIN, &default_font);
+        sheet.next_line(TEXT_SIZE / 2.0);
+
+        for text in &eff.texts {
+            for (i, line) in wrap(&font.metrics, &text.display, TEXT_SIZE, text_width_max).iter().enumerate() {
+                sheet.next_line(TEXT_SIZE);
+
+                if let (true, 0, Some((time, _))) = (timestamps, i, text.fragments.first()) {
+                    sheet.layer.set_fill_color(Color::Rgb(Rgb::new(0.5, 0.5, 0.5, None)));
+                    sheet.write(&format_time(*time), TIMESTAMP_SIZE, MARGIN, &default_font);
+                    sheet.layer.set_fill_color(Color::Rgb(Rgb::new(0.0, 0.0, 0.0, None)));
+                }
+
+                sheet.write(line, TEXT_SIZE, text_x, font);
+            }
+        }
+    }
+
+    sheet.document.save_to_bytes().map_err(|e| KfnParseError::Pdf(e.to_string()))
+}
@@ -0,0 +1,78 @@
+use std::io::Write;
+
+use image::{Delay, Frame, RgbaImage};
+use image::codecs::gif::{GifEncoder, Repeat};
+
+use crate::KfnParseError;
+use crate::kfn_ini::KfnIni;
+use crate::kfn_lyrics::synced_lines;
+use crate::kfn_render::frame::FrameRenderer;
+
+/// The speed of the color quantization of the GIF frames, from 1 to 30. Faster is less accurate.
+const GIF_SPEED: i32 = 10;
+/// The largest width and height of a GIF.
+const MAX_GIF_SIZE: u32 = 65535;
+
+/// The settings of an animated GIF preview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preview {
+    /// The length of the preview in seconds, from the first sync.
+    pub seconds: u32,
+    pub width: u32,
+    pub height: u32,
+    pub fps: u32,
+}
+
+impl Default for Preview {
+    fn default() -> Self {
+        Self { seconds: 10, width: 320, height: 240, fps: 10 }
+    }
+}
+
+/// Returns the time of the first sync of the text layers in milliseconds, or 0 if there are no syncs.
+pub fn first_sync(song: &KfnIni) -> usize {
+    synced_lines(song).first().map(|(_, text)| text.fragments[0].0 * 10).unwrap_or(0)
+}
+
+/// Writes the frames of the player from the start time in milliseconds as an endlessly looping GIF.
+/// Consecutive frames that are the same are merged into one longer frame.
+/// The size of the renderer has to fit into a GIF.
+pub fn write_preview<W: Write>(writer: &mut W, renderer: &mut FrameRenderer, start_ms: usize, preview: &Preview) -> Result<(), KfnParseError> {
+
+    let (width, height) = (renderer.width, renderer.height);
+    if width == 0 || height == 0 || width > MAX_GIF_SIZE || height > MAX_GIF_SIZE {
+        return Err(KfnParseError::InvalidFrameSize { width, height });
+    }
+
+    let fps = preview.fps.max(1);
+    let end_ms = start_ms + preview.seconds as usize * 1000;
+
+    // the frames with their length in milliseconds
+    let mut frames: Vec<(RgbaImage, u32)> = Vec::new();
+    let mut time = start_ms;
+
+    for (n, frame) in renderer.frames(start_ms, end_ms, fps).enumerate() {
+        let next = (start_ms + (n + 1) * 1000 / fps as usize).min(end_ms);
+        let duration = (next - time) as u32;
+        time = next;
+
+        match frames.last_mut() {
+            Some((last, last_duration)) if *last == frame => *last_duration += duration,
+            _ => frames.push((frame, duration)),
+        }
+    }
+
+    write_gif(writer, frames)
+}
+
+/// Writes an endlessly looping GIF. The colors of every frame are quantized separately.
+fn write_gif<W: Write>(writer: &mut W, frames: Vec<(RgbaImage, u32)>) -> Result<(), KfnParseError> {
+
+    let mut encoder = GifEncoder::new_with_speed(writer, GIF_SPEED);
+    encoder.set_repeat(Repeat::Infinite)?;
+    encoder.encode_frames(frames.into_iter().map(|(image, duration)| {
+        Frame::from_parts(image, 0, 0, Delay::from_numer_denom_ms(duration, 1))
+    }))?;
+
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::io::Write;
+
+use image::RgbaImage;
+use rodio::Source;
+
+use crate::KfnParseError;
+use crate::kfn_render::frame::FrameRenderer;
+
+/// The audio track of an exported video.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum AudioTrack {
+    /// The main track, usually without the vocals.
+    #[default]
+    Main,
+    /// The track with the vocals, mixed with the main track if it doesn't replace it, like in the player.
+    Vocal,
+}
+
+/// Decoded audio as interleaved 16 bit samples.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Audio {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+impl Audio {
+    /// Decodes an audio file, like an MP3, optionally mixed with another one.
+    /// The mixed audio is converted to the channels and the sample rate of the first one.
+    pub fn decode(data: Vec<u8>, mix: Option<Vec<u8>>) -> Result<Self, KfnParseError> {
+
+        let decoder = |data: Vec<u8>| rodio::Decoder::new(std::io::Cursor::new(data))
+            .map_err(|e| KfnParseError::InvalidAudio(e.to_string()));
+
+        let source = decoder(data)?;
+        let (channels, sample_rate) = (source.channels(), source.sample_rate());
+
+        let samples = match mix {
+            Some(data) => source.mix(decoder(data)?).collect(),
+            None => source.collect(),
+        };
+
+        Ok(Self { channels, sample_rate, samples })
+    }
+
+    /// Returns the length of the audio in milliseconds.
+    pub fn duration(&self) -> usize {
+        if self.channels == 0 || self.sample_rate == 0 {
+            return 0;
+        }
+        (self.samples.len() as u64 * 1000 / self.channels as u64 / self.sample_rate as u64) as usize
+    }
+
+    /// Writes the audio as a 16 bit PCM WAV file.
+    pub fn write_wav<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+
+        let data_size = (self.samples.len() * 2) as u32;
+        let block_align = self.channels * 2;
+
+        writer.write_all(b"RIFF")?;
+        writer.write_all(&(36 + data_size).to_le_bytes())?;
+        writer.write_all(b"WAVE")?;
+
+        writer.write_all(b"fmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        // PCM
+        writer.write_all(&1u16.to_le_bytes())?;
+        writer.write_all(&self.channels.to_le_bytes())?;
+        writer.write_all(&self.sample_rate.to_le_bytes())?;
+        writer.write_all(&(self.sample_rate * block_align as u32).to_le_bytes())?;
+        writer.write_all(&block_align.to_le_bytes())?;
+        writer.write_all(&16u16.to_le_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&data_size.to_le_bytes())?;
+        let data: Vec<u8> = self.samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        writer.write_all(&data)?;
+
+        Ok(())
+    }
+}
+
+/// Writes the frames of the player from the start until the given length in milliseconds as a YUV4MPEG2 stream,
+/// with 4:2:0 chroma subsampling in the limited range of BT.601.
+pub fn write_y4m<W: Write>(writer: &mut W, renderer: &mut FrameRenderer, duration_ms: usize, fps: u32) -> std::io::Result<()> {
+
+    writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg", renderer.width, renderer.height, fps)?;
+
+    for frame in renderer.frames(0, duration_ms, fps) {
+        writer.write_all(b"FRAME\n")?;
+        writer.write_all(&to_yuv420(&frame))?;
+    }
+
+    Ok(())
+}
+
+/// Converts a frame into the planes of 4:2:0 YCbCr. The chroma planes are averaged from blocks of 2x2 pixels.
+pub fn to_yuv420(frame: &RgbaImage) -> Vec<u8> {
+
+    let (width, height) = frame.dimensions();
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+    let mut y_plane = Vec::with_capacity((width * height) as usize);
+    let mut u_plane = Vec::with_capacity((chroma_width * chroma_height) as usize);
+    let mut v_plane = Vec::with_capacity((chroma_width * chroma_height) as usize);
+
+    for pixel in frame.pixels() {
+        let [r, g, b, _] = pixel.0.map(|c| c as f32);
+        y_plane.push((16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8);
+    }
+
+    for cy in 0..chroma_height {
+        for cx in 0..chroma_width {
+            let (mut r, mut g, mut b, mut count) = (0.0, 0.0, 0.0, 0.0);
+            for y in cy * 2..(cy * 2 + 2).min(height) {
+                for x in cx * 2..(cx * 2 + 2).min(width) {
+                    let pixel = frame.get_pixel(x, y);
+                    r += pixel[0] as f32;
+                    g += pixel[1] as f32;
+                    b += pixel[2] as f32;
+                    count += 1.0;
+                }
+            }
+            let (r, g, b) = (r / count, g / count, b / count);
+            u_plane.push((128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8);
+            v_plane.push((128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8);
+        }
+    }
+
+    y_plane.append(&mut u_plane);
+    y_plane.append(&mut v_plane);
+    y_plane
+}
@@ -4,18 +4,20 @@ pub mod kfn_thread {
 
     use rodio::Source;
 
-    use crate::{Kfn, helpers::event::Event};
+    use crate::{Kfn, KfnParseError, helpers::event::Event};
 
     impl Kfn {
-        pub fn play(&mut self) -> (crossbeam::channel::Sender<String>, crossbeam::channel::Receiver<Event>) {
+        /// Start playback of the audio in a separate thread.
+        /// Returns the channel for sending commands to the thread, and the channel receiving the events.
+        pub fn play(&mut self) -> Result<(crossbeam::channel::Sender<String>, crossbeam::channel::Receiver<Event>), KfnParseError> {
 
             // initialize channels for communicating
             // between the player and the lib
             let (sender_player, receiver_caller): (crossbeam::channel::Sender<Event>, crossbeam::channel::Receiver<Event>) = crossbeam::channel::unbounded();
             let (sender_caller, receiver_player): (crossbeam::channel::Sender<String>, crossbeam::channel::Receiver<String>) = crossbeam::channel::unbounded();
             // read audio file INTO MEMORY
-            let main_source_name = self.data.song.get_source_name();
-            let main_source: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(self.data.get_entry_by_name(&main_source_name).unwrap().file_bin);
+            let main_source_name = self.data.song.get_source_name()?;
+            let main_source: std::io::Cursor<Vec<u8>> = std::io::Cursor::new(self.read_entry(&main_source_name)?);
     
             let secondary_source_name = self.data.song.get_secondary_source();
     
@@ -27,7 +29,7 @@ pub mod kfn_thread {
                     // this is needed, because the line contains additional comma separated -1,0,1 values, which indicate,
                     // if the track is only guide vocal, replaces original, etc... which are not needed here
                     let filename_split: Vec<&str> = filename.split(',').collect();
-                    Some(std::io::Cursor::new(self.data.get_entry_by_name(&filename_split[filename_split.len()-1]).unwrap().file_bin))
+                    Some(std::io::Cursor::new(self.read_entry(filename_split[filename_split.len()-1])?))
                 }
                 None => None
             };
@@ -257,7 +259,7 @@ pub mod kfn_thread {
                 
             });
     
-            (sender_caller, receiver_caller)
+            Ok((sender_caller, receiver_caller))
         }
     }
 
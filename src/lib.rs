@@ -8,6 +8,12 @@ pub mod kfn_data;
 pub mod kfn_header;
 /// The Song.ini file, containing essential information about the KFN.
 pub mod kfn_ini;
+/// Builder for authoring a KFN file from scratch.
+pub mod kfn_builder;
+/// Converting the lyrics from and to other karaoke and subtitle formats.
+pub mod kfn_lyrics;
+/// Rendering the lyrics into graphics and video formats.
+pub mod kfn_render;
 /// Window for displaying the KFN file.
 pub mod kfn_player;
 /// Default fonts module
@@ -16,17 +22,26 @@ pub mod fonts;
 pub mod kfn_thread;
 
 
+#[cfg(feature = "html")]
+use std::collections::HashMap;
+
 // helpers
-use crate::helpers::Entry;
+use crate::helpers::{Entry, SourceLocation};
+use crate::helpers::crypt;
+use crate::helpers::source::{KfnSource, Source};
 use crate::helpers::file_type::FileType;
 use crate::helpers::file_type::ToBinary;
 use crate::helpers::event::{Event, EventType};
 
 // header
-use crate::kfn_header::KfnHeader;
+use crate::kfn_header::{KfnHeader, TagValue};
 
 use kfn_data::KfnData;
 
+// rendering
+use kfn_render::video::{Audio, AudioTrack};
+use kfn_render::preview::Preview;
+
 // player
 use kfn_player::KfnPlayer;
 
@@ -34,14 +49,25 @@ use kfn_player::KfnPlayer;
 
 #[derive(derivative::Derivative)]
 #[derivative(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// Struct representing a .kfn file and it's components, like the header and data.
+/// With the `serde` feature, the header and the data can be serialized, and deserialized into a file that can be exported.
+/// The entries of a lazily parsed file have to be loaded with `load_all` first, serializing fails otherwise.
 pub struct Kfn {
     
-    /// The binary data in a vector of bytes.
-    file_data: Vec<u8>,
+    /// The source of the binary data, like a vector of bytes or a file.
+    #[derivative(Debug="ignore")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    source: Source,
 
     /// The read head, used in calculating the offset from the directory end.
+    #[cfg_attr(feature = "serde", serde(skip))]
     read_head: usize,
+
+    /// If true, parsing only reads the header, the directory and the Song.ini.
+    /// The other files are read from the source on demand.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    lazy: bool,
     
     /// The header data of the file.
     pub header: KfnHeader,
@@ -51,35 +77,179 @@ pub struct Kfn {
 
 }
 
+/// Errors, that can occur while reading, modifying or writing a KFN file.
 #[derive(Debug)]
 pub enum KfnParseError {
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
     InvalidHeaderSignature(String),
     Utf8ConversionError,
+    /// An entry is encrypted, but the FLID in the header is not a valid AES key.
+    InvalidEncryptionKey,
+    /// The file ended before the requested data. Contains the read position and the requested length.
+    TruncatedData { offset: usize, length: usize },
+    /// A directory entry points outside of the file.
+    InvalidDirectoryOffset { filename: String, offset: usize, length: usize },
+    /// No entry exists with the given name.
+    EntryNotFound(String),
+    /// The payload of the entry is not loaded, but it is needed in memory.
+    EntryNotLoaded(String),
+    /// The Song.ini file could not be parsed.
+    InvalidIni(String),
+    /// A section is missing from the Song.ini file.
+    MissingIniSection(String),
+    /// A key is missing from a section of the Song.ini file.
+    MissingIniValue { section: String, key: String },
+    /// A value in the Song.ini file is malformed, like an Eff, Anim or Sync line.
+    InvalidIniValue { section: String, key: String, value: String },
+    /// A trajectory value could not be parsed.
+    InvalidTrajectory(String),
+    /// An action of an Anim line could not be parsed.
+    InvalidAction(String),
+    /// A line of an imported lyrics file is malformed. Contains the line number, starting from 1, and the line.
+    InvalidLyrics { line: usize, value: String },
+    /// A MIDI file could not be parsed.
+    InvalidMidi(String),
+    /// The player could not be started.
+    Player(String),
+    /// An image could not be encoded or written.
+    Image(String),
+    /// An audio file could not be decoded.
+    InvalidAudio(String),
+    /// A PDF document could not be written.
+    Pdf(String),
+    /// The size of rendered frames is zero, or too large for the format.
+    InvalidFrameSize { width: u32, height: u32 },
+}
+
+impl std::fmt::Display for KfnParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KfnParseError::Io(e) => write!(f, "IO error: {}", e),
+            KfnParseError::InvalidHeaderSignature(s) => write!(f, "invalid header signature: {}", s),
+            KfnParseError::Utf8ConversionError => write!(f, "invalid UTF-8 string"),
+            KfnParseError::InvalidEncryptionKey => write!(f, "encrypted entry, but the FLID is not a valid key"),
+            KfnParseError::TruncatedData { offset, length } => 
+                write!(f, "file truncated, {} bytes requested at offset {}", length, offset),
+            KfnParseError::InvalidDirectoryOffset { filename, offset, length } => 
+                write!(f, "entry {} points outside of the file ({} bytes at offset {})", filename, length, offset),
+            KfnParseError::EntryNotFound(name) => write!(f, "entry not found: {}", name),
+            KfnParseError::EntryNotLoaded(name) => write!(f, "entry not loaded: {}", name),
+            KfnParseError::InvalidIni(e) => write!(f, "invalid Song.ini: {}", e),
+            KfnParseError::MissingIniSection(section) => write!(f, "missing section [{}] in Song.ini", section),
+            KfnParseError::MissingIniValue { section, key } => 
+                write!(f, "missing key {} in section [{}] of Song.ini", key, section),
+            KfnParseError::InvalidIniValue { section, key, value } => 
+                write!(f, "invalid value for {} in section [{}] of Song.ini: {}", key, section, value),
+            KfnParseError::InvalidTrajectory(s) => write!(f, "invalid trajectory: {}", s),
+            KfnParseError::InvalidAction(s) => write!(f, "invalid action: {}", s),
+            KfnParseError::InvalidLyrics { line, value } => write!(f, "invalid lyrics in line {}: {}", line, value),
+            KfnParseError::InvalidMidi(e) => write!(f, "invalid MIDI file: {}", e),
+            KfnParseError::Player(e) => write!(f, "player error: {}", e),
+            KfnParseError::Image(e) => write!(f, "image error: {}", e),
+            KfnParseError::InvalidAudio(e) => write!(f, "invalid audio file: {}", e),
+            KfnParseError::Pdf(e) => write!(f, "PDF error: {}", e),
+            KfnParseError::InvalidFrameSize { width, height } => write!(f, "invalid frame size: {}x{}", width, height),
+        }
+    }
+}
+
+impl std::error::Error for KfnParseError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            KfnParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for KfnParseError {
+    fn from(e: std::io::Error) -> Self {
+        KfnParseError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for KfnParseError {
+    fn from(e: image::ImageError) -> Self {
+        KfnParseError::Image(e.to_string())
+    }
 }
 
 impl Kfn {
 
     /// Constructor for creating a Kfn struct from an existing file.
     /// Takes the filename as parameter.
-    pub fn open(filename: &str) -> Self {
+    pub fn open(filename: &str) -> Result<Self, KfnParseError> {
+        Ok(Self::from_bytes(std::fs::read(filename)?))
+    }
+
+    /// Constructor for creating a Kfn struct from a file already in memory.
+    pub fn from_bytes(file_data: Vec<u8>) -> Self {
+        Self::from_source(Source::from_bytes(file_data), false)
+    }
+
+    /// Constructor for creating a Kfn struct from a source, either parsed lazily or not.
+    fn from_source(source: Source, lazy: bool) -> Self {
         Self { 
-            file_data: match std::fs::read(filename) {
-                Ok(file) => file,
-                Err(e) => panic!("File not found! {}", e),
-            },
+            source,
             read_head: usize::default(),
+            lazy,
             header: KfnHeader::default(),
             data: KfnData::new(),
 
          }
     }
 
+    /// Constructor for creating a Kfn struct from any reader, like an archive entry or an upload body.
+    /// The file is read from the current position of the reader to its end.
+    pub fn from_reader<R: std::io::Read + std::io::Seek>(reader: &mut R) -> Result<Self, KfnParseError> {
+
+        // measure the remaining length, so the buffer is only allocated once
+        let start = reader.stream_position()?;
+        let end = reader.seek(std::io::SeekFrom::End(0))?;
+        reader.seek(std::io::SeekFrom::Start(start))?;
+
+        let mut file_data: Vec<u8> = Vec::with_capacity(end.saturating_sub(start) as usize);
+        reader.read_to_end(&mut file_data)?;
+
+        Ok(Self::from_bytes(file_data))
+    }
+
+    /// Constructor for opening a file lazily.
+    /// Parsing only reads the header, the directory and the Song.ini, the other files are read on demand.
+    pub fn open_lazy(filename: &str) -> Result<Self, KfnParseError> {
+        let file = std::io::BufReader::new(std::fs::File::open(filename)?);
+        Self::from_reader_lazy(file)
+    }
+
+    /// Constructor for reading lazily from any reader, starting at its current position.
+    /// Parsing only reads the header, the directory and the Song.ini, the other files are read on demand.
+    pub fn from_reader_lazy<R: KfnSource + 'static>(reader: R) -> Result<Self, KfnParseError> {
+        Ok(Self::from_source(Source::new(Box::new(reader))?, true))
+    }
+
+    /// Constructor for opening a file lazily through a memory map.
+    /// Parsing only reads the header, the directory and the Song.ini, the other files are read on demand.
+    /// 
+    /// The file must not be modified while it is mapped, as that is undefined behaviour.
+    #[cfg(feature = "mmap")]
+    pub fn open_mmap(filename: &str) -> Result<Self, KfnParseError> {
+        let file = std::fs::File::open(filename)?;
+        // SAFETY: the map is read only, the caller guarantees that the file is not modified meanwhile
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::from_reader_lazy(std::io::Cursor::new(map))
+    }
+
     /// Constructor for creating a new Kfn struct.
+    /// A fresh random FLID is generated, which is used for encrypting entries on export.
     pub fn new() -> Self {
+        let header = KfnHeader { flid: crypt::generate_key(), ..Default::default() };
+
         Self { 
-            file_data: Vec::new(), 
+            source: Source::empty(), 
             read_head: 0, 
-            header: KfnHeader::default(), 
+            lazy: false,
+            header, 
             data: KfnData::new(),
 
         }
@@ -89,8 +259,11 @@ impl Kfn {
     /// Method for parsing the file itself.
     pub fn parse(&mut self) -> Result<bool, KfnParseError> {
         println!("Started parsing KFN file.");
+        self.read_head = 0;
+        self.source.seek(0)?;
+
         // read file signature
-        let signature = match String::from_utf8(self.read_bytes(4)) {
+        let signature = match String::from_utf8(self.read_bytes(4)?) {
             Ok(s) => s,
             Err(_) => return Err(KfnParseError::Utf8ConversionError),
         };
@@ -98,132 +271,57 @@ impl Kfn {
         if signature != "KFNB" {
             return Err(KfnParseError::InvalidHeaderSignature(signature));
         }
+        self.header.tags.clear();
         
         // reading the header
         loop {
             // get signature
-            let signature = match String::from_utf8(self.read_bytes(4)) {
+            let signature = match String::from_utf8(self.read_bytes(4)?) {
                 Ok(s) => s,
                 Err(_) => return Err(KfnParseError::Utf8ConversionError),
             };
             // get type of the line
-            let l_type = self.read_byte();
-            let len_or_value = self.read_dword();
+            let l_type = self.read_byte()?;
+            let len_or_value = self.read_dword()?;
 
             // match for line type > if type 1, it's a value, if type 2 -> it contains header information
-            match l_type {
-                1 => {
-
-                    match signature.as_str() {
-
-                        "DIFM" => {
-                            self.header.diff_men = len_or_value;
-                        },
-                        "DIFW" => {
-                            self.header.diff_women = len_or_value;
-                        },
-                        "GNRE" => {
-                            self.header.genre = len_or_value;
-                        },
-                        "SFTV" => {
-                            self.header.sftv = len_or_value;
-                        },
-                        "MUSL" => {
-                            self.header.musl = len_or_value;
-                        },
-                        "ANME" => {
-                            self.header.anme = len_or_value;
-                        },
-                        "TYPE" => {
-                            self.header.kfn_type = len_or_value;
-                        },
-                        "RGHT" => {
-                            self.header.rght = len_or_value;
-                        },
-                        "PROV" => {
-                            self.header.prov = len_or_value;
-                        },
-                        _ => println!("Unknown header entry in file."),
-                    }
-                },
-                2 => {
-                    // get data into buffer
-                    let buffer = self.read_bytes(len_or_value);
-                    let buffer_str = String::from_utf8(buffer.clone()).unwrap_or("Unknown".to_string());
-                    // match header info and insert into header
-                    match signature.as_str() {
-
-                        "FLID" => {
-                            self.header.flid = buffer_str;
-                        },
-                        "LANG" => {
-                            self.header.language = buffer_str
-                        },
-                        "TITL" => {
-                            self.header.title = buffer_str
-                        },
-                        "ALBM" => {
-                            self.header.album = buffer_str
-                        },
-                        "ARTS" => {
-                            self.header.artist = buffer_str
-                        },
-                        "COMP" => {
-                            self.header.composer = buffer_str
-                        },
-                        "COPY" => {
-                            self.header.copyright = buffer_str
-                        },
-                        "SORC" => {
-                            self.header.source_file = buffer_str
-                        },
-                        "YEAR" => {
-                            self.header.year = buffer_str
-                        },
-                        "TRAK" => {
-                            self.header.trak = buffer_str
-                        },
-                        "KFNZ" => {
-                            self.header.karafunizer = buffer_str
-                        },
-                        "IDUS" => {
-                            self.header.idus = buffer_str
-                        },
-                        _ => ()
-                    }
-                   
-                },
-                _ => {
+            let value = match l_type {
+                1 => TagValue::Dword(len_or_value),
+                2 => TagValue::Data(self.read_bytes(len_or_value)?),
+                _ => TagValue::Other(l_type, len_or_value),
+            };
 
-                },
-            }
+            // every tag is kept, so unknown ones survive the export
+            self.header.read_tag(&signature, value);
 
             if signature == "ENDH" {
                 break;
             }
         }
 
-        // reading the directory
-        let num_files = self.read_dword();
+        // reading the directory, replacing the entries of a previous parse
+        self.data.entries.clear();
+        self.data.offset_dir_end = 0;
+        let num_files = self.read_dword()?;
         println!("# of files: {}", num_files);
 
         for _ in 0..num_files {
 
-            let filename_len = self.read_dword();
-            let filename = match String::from_utf8(self.read_bytes(filename_len)) {
+            let filename_len = self.read_dword()?;
+            let filename = match String::from_utf8(self.read_bytes(filename_len)?) {
                 Ok(s) => s,
                 Err(_) => return Err(KfnParseError::Utf8ConversionError),
             };
-            let file_type = FileType::from(self.read_dword());
-            let len1 = self.read_dword() as usize;
-            let offset = self.read_dword() as usize;
-            let len2 = self.read_dword() as usize;
-            let flags = self.read_dword() as usize;
+            let file_type = FileType::from(self.read_dword()?);
+            let len1 = self.read_dword()? as usize;
+            let offset = self.read_dword()? as usize;
+            let len2 = self.read_dword()? as usize;
+            let flags = self.read_dword()? as usize;
 
             let buf: Vec<u8> = Vec::default();
 
             self.data.entries.push(Entry {
-                filename, file_type, len1, offset, len2, flags, file_bin: buf,
+                filename, file_type, len1, offset, len2, flags, file_bin: buf, deferred: None,
             });
             
         }
@@ -232,52 +330,163 @@ impl Kfn {
         // readjust offset
         for i in 0..self.data.entries.len() {
             self.data.entries[i].offset += self.data.offset_dir_end;
-            self.data.entries[i].file_bin = 
-                        Vec::from(
-                            &self.file_data[
-                                self.data.entries[i].offset..self.data.entries[i].offset +   self.data.entries[i].len1
-                            ]
-                        );
+
+            // until loaded, the payload is only in the source
+            let entry = &mut self.data.entries[i];
+            entry.deferred = Some(SourceLocation { 
+                offset: entry.offset, len1: entry.len1, len2: entry.len2, flags: entry.flags 
+            });
+
+            // in lazy mode, only the Song.ini is needed right away
+            if !self.lazy || self.data.entries[i].file_type == FileType::SongIni {
+                self.load_entry_by_id(i)?;
+            }
         }
 
-        self.data.read_ini();
+        self.data.read_ini()?;
         
-        self.data.song.load_eff();
+        self.data.song.load()?;
 
         Ok(true)
     }
 
+    // ----------------
+    // KFN ENTRY ACCESS
+    // ----------------
+
+    /// Returns a reader over the plain data of the entry.
+    /// Entries that are not loaded yet are read from the source, decrypting them on the fly if needed.
+    pub fn entry_reader<'a>(&'a mut self, entry: &'a Entry) -> Result<Box<dyn std::io::Read + 'a>, KfnParseError> {
+
+        let location = match entry.deferred {
+            Some(location) => location,
+            None => return Ok(Box::new(std::io::Cursor::new(&entry.file_bin))),
+        };
+
+        // the stored payload is len2 long, which is the padded length for encrypted files
+        let in_bounds = match location.offset.checked_add(location.len2) {
+            Some(end) => end <= self.source.len() && (crypt::is_encrypted(location.flags) || location.len1 <= location.len2),
+            None => false,
+        };
+        if !in_bounds {
+            return Err(KfnParseError::InvalidDirectoryOffset { 
+                filename: entry.filename.clone(),
+                offset: location.offset,
+                length: location.len2,
+            });
+        }
+
+        let key = self.header.flid.clone();
+        let stored = self.source.take(location.offset, location.len2)?;
+
+        if crypt::is_encrypted(location.flags) {
+            match crypt::DecryptReader::new(&key, stored, location.len1) {
+                Some(reader) => Ok(Box::new(reader)),
+                None => Err(KfnParseError::InvalidEncryptionKey),
+            }
+        } else {
+            Ok(Box::new(std::io::Read::take(stored, location.len1 as u64)))
+        }
+    }
+
+    /// Returns the plain data of an entry by file name, reading it from the source if it is not loaded yet.
+    pub fn read_entry(&mut self, name: &str) -> Result<Vec<u8>, KfnParseError> {
+
+        match self.data.get_entry_by_name(name) {
+            Some(entry) => self.read_entry_data(entry),
+            None => Err(KfnParseError::EntryNotFound(name.to_string())),
+        }
+    }
+
+    /// Returns the plain data of the entry, reading it from the source if it is not loaded yet.
+    fn read_entry_data(&mut self, entry: Entry) -> Result<Vec<u8>, KfnParseError> {
+
+        let location = match entry.deferred {
+            Some(location) => location,
+            None => return Ok(entry.file_bin),
+        };
+
+        let mut data: Vec<u8> = Vec::with_capacity(location.len1);
+        
+        self.entry_reader(&entry)?.read_to_end(&mut data).map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => KfnParseError::TruncatedData { offset: location.offset, length: location.len2 },
+            _ => KfnParseError::Io(e),
+        })?;
+
+        Ok(data)
+    }
+
+    /// Loads the data of an entry by file name into memory, if it is not loaded yet.
+    pub fn load_entry(&mut self, name: &str) -> Result<(), KfnParseError> {
+
+        match self.data.entries.iter().position(|entry| entry.filename == name) {
+            Some(id) => self.load_entry_by_id(id),
+            None => Err(KfnParseError::EntryNotFound(name.to_string())),
+        }
+    }
+
+    /// Loads the data of every entry into memory.
+    pub fn load_all(&mut self) -> Result<(), KfnParseError> {
+
+        for id in 0..self.data.entries.len() {
+            self.load_entry_by_id(id)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the data of an entry by ID into memory, if it is not loaded yet.
+    fn load_entry_by_id(&mut self, id: usize) -> Result<(), KfnParseError> {
+
+        if self.data.entries[id].deferred.is_none() {
+            return Ok(());
+        }
+
+        let data = self.read_entry_data(self.data.entries[id].clone())?;
+
+        self.data.entries[id].file_bin = data;
+        self.data.entries[id].deferred = None;
+
+        Ok(())
+    }
+
     // ----------------------
     // KFN MANIPULATION BLOCK
     // ----------------------
      
     /// Add file
-    pub fn add_file(&mut self, source: &str) {
+    pub fn add_file(&mut self, source: &str) -> Result<(), KfnParseError> {
 
-        self.data.add_entry_from_file(source);
-        self.update();
+        self.data.add_entry_from_file(source)?;
+        self.update()
     }
 
     /// Remove file
-    pub fn remove_file(&mut self, target: &str) {
+    pub fn remove_file(&mut self, target: &str) -> Result<(), KfnParseError> {
 
         self.data.remove_entry_by_name(target);
-        self.update();
+        self.update()
+    }
+
+    /// Sets whether the file should be encrypted with the FLID key on export.
+    pub fn set_encrypted(&mut self, target: &str, encrypted: bool) {
+
+        self.data.set_encrypted(target, encrypted);
     }
 
     /// Takes the source filename and sets it as the song to play during playback.
-    pub fn set_source(&mut self, target: &str) {
+    pub fn set_source(&mut self, target: &str) -> Result<(), KfnParseError> {
 
         self.header.source_file = target.to_string();
         self.data.song.set_source(target);
-        self.update();
+        self.update()
     }
 
     /// Update the ini file from header
-    pub fn update(&mut self) {
+    pub fn update(&mut self) -> Result<(), KfnParseError> {
 
         self.data.song.populate_from_header(&self.header);
-        self.data.update_ini();
+        self.data.update_ini()
     }
 
     /// Get texts with syncs in.
@@ -346,21 +555,291 @@ impl Kfn {
                 continue;
             }
             for text in &eff.texts {
-                events.push(Event {
-                    event_type: EventType::Text(text.to_owned()),
-                    time: text.fragments[0].0
-                })
+                // lines without syncs can't be shown at any time
+                if let Some((time, _)) = text.fragments.first() {
+                    events.push(Event {
+                        event_type: EventType::Text(text.to_owned()),
+                        time: *time,
+                    })
+                }
             }
         }
         events
     }
 
+    /// Returns the lyrics of the text layers as LRC.
+    /// The enhanced format has a timestamp for every fragment, not just every line.
+    pub fn to_lrc(&self, enhanced: bool) -> String {
+        kfn_lyrics::lrc::to_lrc(&self.header, &self.data.song, enhanced)
+    }
+
+    /// Returns the lyrics of the text layers as ASS karaoke subtitles, with a style for every layer.
+    pub fn to_ass(&self) -> String {
+        kfn_lyrics::ass::to_ass(&self.header, &self.data.song)
+    }
+
+    /// Replaces the text layers with the karaoke events of an ASS or SSA script, and updates the Song.ini.
+    /// Every style used becomes a layer, with the font and the colors of the style.
+    pub fn import_ass(&mut self, ass: &str) -> Result<(), KfnParseError> {
+        let effs = kfn_lyrics::ass::from_ass(ass, &mut self.header)?;
+        kfn_lyrics::set_text_layers(&mut self.data.song, effs);
+        self.update()
+    }
+
+    /// Returns the lyrics as SRT subtitles, a cue for every line.
+    pub fn to_srt(&self) -> String {
+        kfn_lyrics::subtitles::to_srt(&self.data.song)
+    }
+
+    /// Returns the lyrics as WebVTT subtitles, optionally with the timestamps of the fragments in the cues.
+    pub fn to_vtt(&self, word_timestamps: bool) -> String {
+        kfn_lyrics::subtitles::to_vtt(&self.header, &self.data.song, word_timestamps)
+    }
+
+    /// Returns the lyrics as an UltraStar TXT song at the given BPM, with the same placeholder pitch for every note.
+    pub fn to_ultrastar(&self, bpm: f64) -> String {
+        kfn_lyrics::ultrastar::to_ultrastar(&self.header, &self.data.song, bpm)
+    }
+
+    /// Replaces the text layers with the notes of an UltraStar TXT song, and updates the Song.ini.
+    /// Every player of a duet becomes a layer.
+    pub fn import_ultrastar(&mut self, txt: &str) -> Result<(), KfnParseError> {
+        let effs = kfn_lyrics::ultrastar::from_ultrastar(txt, &mut self.header)?;
+        kfn_lyrics::set_text_layers(&mut self.data.song, effs);
+        self.update()
+    }
+
+    /// Replaces the text layers with the lyrics of a MIDI karaoke (.kar) file, and updates the Song.ini.
+    /// The audio has to be added separately, and set with `set_source`.
+    pub fn import_kar(&mut self, data: &[u8]) -> Result<(), KfnParseError> {
+        let eff = kfn_lyrics::kar::from_kar(data, &mut self.header)?;
+        kfn_lyrics::set_text_layers(&mut self.data.song, vec![eff]);
+        self.update()
+    }
+
+    /// Returns the lyrics as a CD+G graphics stream. The embedded fonts of the layers are loaded if needed.
+    pub fn to_cdg(&mut self) -> Result<Vec<u8>, KfnParseError> {
+        self.load_fonts()?;
+        Ok(kfn_render::cdg::to_cdg(&self.data))
+    }
+
+    /// Writes the lyrics as CD+G into the file, and extracts the main audio next to it,
+    /// with the same name and the extension of the audio, making an MP3+G pair.
+    pub fn export_cdg(&mut self, filename: &str) -> Result<(), KfnParseError> {
+
+        let source = self.data.song.get_source_name()?;
+        let entry = self.data.get_entry_by_name(&source).ok_or(KfnParseError::EntryNotFound(source.clone()))?;
+
+        let path = std::path::Path::new(filename);
+        let extension = std::path::Path::new(&source).extension().unwrap_or_default();
+        self.extract(entry, &path.with_extension(extension).to_string_lossy())?;
+
+        std::fs::write(path, self.to_cdg()?)?;
+
+        Ok(())
+    }
+
+    /// Returns a renderer for the frames of the player in the given size, without opening a window.
+    /// The background images and the fonts are loaded first.
+    pub fn frame_renderer(&mut self, width: u32, height: u32) -> Result<kfn_render::frame::FrameRenderer<'_>, KfnParseError> {
+        self.load_fonts()?;
+        self.load_backgrounds()?;
+        kfn_render::frame::FrameRenderer::new(&self.data, width, height)
+    }
+
+    /// Renders the frame of the player at the given time in milliseconds, unlike the centiseconds of the Song.ini.
+    pub fn render_frame(&mut self, time_ms: usize, width: u32, height: u32) -> Result<image::RgbaImage, KfnParseError> {
+        Ok(self.frame_renderer(width, height)?.render(time_ms))
+    }
+
+    /// Writes the frame of the player at the given time in milliseconds into a PNG file.
+    pub fn export_frame(&mut self, time_ms: usize, width: u32, height: u32, filename: &str) -> Result<(), KfnParseError> {
+        self.render_frame(time_ms, width, height)?.save_with_format(filename, image::ImageFormat::Png)?;
+        Ok(())
+    }
+
+    /// Writes the frames of the player from the start until the end time in milliseconds into a directory,
+    /// as numbered PNG files, like frame_00000.png.
+    pub fn export_frames(&mut self, start_ms: usize, end_ms: usize, fps: u32, width: u32, height: u32, directory: &str) -> Result<(), KfnParseError> {
+
+        std::fs::create_dir_all(directory)?;
+        let directory = std::path::Path::new(directory);
+
+        for (n, frame) in self.frame_renderer(width, height)?.frames(start_ms, end_ms, fps).enumerate() {
+            frame.save_with_format(directory.join(format!("frame_{:05}.png", n)), image::ImageFormat::Png)?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the main track, or the track with the vocals, mixed with the main track if it doesn't replace it.
+    pub fn decode_audio(&mut self, track: AudioTrack) -> Result<Audio, KfnParseError> {
+
+        let main = self.read_entry(&self.data.song.get_source_name()?)?;
+
+        match track {
+            AudioTrack::Main => Audio::decode(main, None),
+            AudioTrack::Vocal => {
+                let vocal = self.data.song.mp3_music.tracks.first().cloned().ok_or(KfnParseError::MissingIniValue {
+                    section: "MP3Music".to_string(), key: "Track0".to_string()
+                })?;
+                let data = self.read_entry(&vocal.filename)?;
+                match vocal.replaces_track() {
+                    true => Audio::decode(data, None),
+                    false => Audio::decode(main, Some(data)),
+                }
+            }
+        }
+    }
+
+    /// Exports the player as an uncompressed YUV4MPEG2 video in the given size and frame rate,
+    /// and the decoded audio track next to it as WAV, with the same name. The video is as long as the audio.
+    pub fn export_video(&mut self, filename: &str, width: u32, height: u32, fps: u32, track: AudioTrack) -> Result<(), KfnParseError> {
+
+        let audio = self.decode_audio(track)?;
+        let path = std::path::Path::new(filename);
+
+        let mut output = std::io::BufWriter::new(std::fs::File::create(path.with_extension("wav"))?);
+        audio.write_wav(&mut output)?;
+        std::io::Write::flush(&mut output)?;
+
+        let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
+        kfn_render::video::write_y4m(&mut output, &mut self.frame_renderer(width, height)?, audio.duration(), fps)?;
+        std::io::Write::flush(&mut output)?;
+
+        Ok(())
+    }
+
+    /// Returns an animated GIF preview of the player, starting at the first sync of the lyrics.
+    pub fn to_preview(&mut self, preview: &Preview) -> Result<Vec<u8>, KfnParseError> {
+
+        let start = kfn_render::preview::first_sync(&self.data.song);
+        let mut output: Vec<u8> = Vec::new();
+        kfn_render::preview::write_preview(&mut output, &mut self.frame_renderer(preview.width, preview.height)?, start, preview)?;
+
+        Ok(output)
+    }
+
+    /// Writes an animated GIF preview of the player into a file, starting at the first sync of the lyrics.
+    pub fn export_preview(&mut self, filename: &str, preview: &Preview) -> Result<(), KfnParseError> {
+        std::fs::write(filename, self.to_preview(preview)?)?;
+        Ok(())
+    }
+
+    /// Returns a self-contained HTML5 page playing the main audio, with the background images and the lyrics
+    /// highlighted in sync. The files are embedded as data URLs.
+    #[cfg(feature = "html")]
+    pub fn to_html(&mut self) -> Result<String, KfnParseError> {
+
+        let mut urls: HashMap<String, String> = HashMap::new();
+
+        for filename in kfn_render::html::assets(&self.data.song)? {
+            if self.data.get_entry_by_name(&filename).is_some() {
+                let data = self.read_entry(&filename)?;
+                urls.insert(filename.clone(), kfn_render::html::data_url(&filename, &data));
+            }
+        }
+
+        kfn_render::html::to_html(&self.header, &self.data.song, &urls)
+    }
+
+    /// Writes an HTML5 page playing the song into a file.
+    /// With `assets`, the files are extracted into a folder next to it, named after the page, like song_files,
+    /// otherwise they are embedded into the page. Entries with a name leaving the folder, like ../song.mp3, are left out.
+    #[cfg(feature = "html")]
+    pub fn export_html(&mut self, filename: &str, assets: bool) -> Result<(), KfnParseError> {
+
+        if !assets {
+            std::fs::write(filename, self.to_html()?)?;
+            return Ok(());
+        }
+
+        let path = std::path::Path::new(filename);
+        let directory = format!("{}_files", path.file_stem().unwrap_or_default().to_string_lossy());
+        let directory_path = path.with_file_name(&directory);
+        std::fs::create_dir_all(&directory_path)?;
+
+        let mut urls: HashMap<String, String> = HashMap::new();
+
+        for filename in kfn_render::html::assets(&self.data.song)? {
+
+            // the names come from the file, so only plain relative paths are written into the folder
+            let plain = std::path::Path::new(&filename).components().all(|component| matches!(component, std::path::Component::Normal(_)));
+            if !plain {
+                continue;
+            }
+
+            if let Some(entry) = self.data.get_entry_by_name(&filename) {
+                self.extract(entry, &directory_path.join(&filename).to_string_lossy())?;
+                urls.insert(filename.clone(), kfn_render::html::relative_url(&directory, &filename));
+            }
+        }
+
+        std::fs::write(path, kfn_render::html::to_html(&self.header, &self.data.song, &urls)?)?;
+
+        Ok(())
+    }
+
+    /// Returns a printable PDF lyric sheet of the text layers, optionally with the time of every line in the margin.
+    pub fn to_pdf(&mut self, timestamps: bool) -> Result<Vec<u8>, KfnParseError> {
+        self.load_fonts()?;
+        kfn_render::pdf::to_pdf(&self.header, &self.data, timestamps)
+    }
+
+    /// Writes a printable PDF lyric sheet of the text layers into a file.
+    pub fn export_pdf(&mut self, filename: &str, timestamps: bool) -> Result<(), KfnParseError> {
+        std::fs::write(filename, self.to_pdf(timestamps)?)?;
+        Ok(())
+    }
+
+    /// Loads the images of the background layer, which are not loaded yet.
+    fn load_backgrounds(&mut self) -> Result<(), KfnParseError> {
+
+        let images: Vec<String> = kfn_render::background_images(&self.data.song).into_iter()
+            .map(|(_, filename)| filename)
+            .filter(|filename| self.data.get_entry_by_name(filename).is_some_and(|entry| entry.deferred.is_some()))
+            .collect();
+
+        for filename in images {
+            self.load_entry(&filename)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the embedded fonts of the layers, which are not loaded yet.
+    fn load_fonts(&mut self) -> Result<(), KfnParseError> {
+
+        let fonts: Vec<String> = self.data.song.effs.iter()
+            .filter_map(|eff| eff.initial_font.as_ref().map(|(filename, _)| filename.clone()))
+            .filter(|filename| self.data.get_entry_by_name(filename).is_some_and(|entry| entry.deferred.is_some()))
+            .collect();
+
+        for filename in fonts {
+            self.load_entry(&filename)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the text layers with the lyrics of an LRC or enhanced LRC file, and updates the Song.ini.
+    /// The title, artist and album tags are set in the header.
+    pub fn import_lrc(&mut self, lrc: &str) -> Result<(), KfnParseError> {
+        let eff = kfn_lyrics::lrc::from_lrc(lrc, &mut self.header)?;
+        kfn_lyrics::set_text_layers(&mut self.data.song, vec![eff]);
+        self.update()
+    }
+
     /// Co
     pub fn get_bg_events(&self) -> Vec<Event> {
         let mut bg_events: Vec<Event> = Vec::new();
         // Select the Eff# fields in the Songs.ini
+        let background = match self.data.song.effs.first() {
+            Some(eff) => eff,
+            None => return bg_events,
+        };
             // Select the Anim# lines
-            for anim in &self.data.song.effs[0].anims {
+            for anim in &background.anims {
                 // Separate the time
                 let time = anim.time;
                 // Go through each AnimEntry for their actions
@@ -382,18 +861,22 @@ impl Kfn {
     
 
     /// Start playback in a separate window.
-    pub fn play_kfn(&mut self) {
+    pub fn play_kfn(&mut self) -> Result<(), KfnParseError> {
 
         // falling back to X11/Xorg, for server side decoration
         std::env::set_var("WAYLAND_DISPLAY", "");
 
 
 
-        let window = speedy2d::Window::new_centered(&self.header.title, (800, 600)).unwrap();
+        // the player needs the images and fonts in memory
+        self.load_all()?;
+
+        let window = speedy2d::Window::new_centered(&self.header.title, (800, 600))
+            .map_err(|e| KfnParseError::Player(e.to_string()))?;
         
         let events = self.get_bg_events();
         //dbg!(&events);
-        let (sender, receiver) = self.play();
+        let (sender, receiver) = self.play()?;
             window.run_loop(
                 KfnPlayer::new(self.data.clone(), 
                 (800, 600), 
@@ -405,18 +888,58 @@ impl Kfn {
     }
 
     /// Exporting to .kfn 
-    pub fn export(&mut self, filename: &str) {
-        
-        let mut data: Vec<u8> = Vec::new();
+    /// Entries flagged as encrypted are encrypted with the FLID key.
+    /// If there is no valid key yet, a new one is generated.
+    pub fn export(&mut self, filename: &str) -> Result<(), KfnParseError> {
         
-        data.append(&mut self.header.to_binary());
-        data.append(&mut self.data.to_binary());
-        
-        std::fs::write(filename, data).unwrap();
+        let mut output = std::io::BufWriter::new(std::fs::File::create(filename)?);
+
+        self.write_to(&mut output)?;
+
+        std::io::Write::flush(&mut output)?;
+
+        Ok(())
+    }
+
+    /// Writing the .kfn into any writer, like a file, a socket or a pipe.
+    /// The directory is calculated up front, then the files are streamed one by one,
+    /// so entries that are not loaded are never fully in memory.
+    /// Entries flagged as encrypted are encrypted with the FLID key.
+    /// If there is no valid key yet, a new one is generated.
+    pub fn write_to<W: std::io::Write>(&mut self, writer: &mut W) -> Result<(), KfnParseError> {
+
+        if self.data.entries.iter().any(|entry| crypt::is_encrypted(entry.flags)) 
+            && self.header.flid.len() != crypt::BLOCK_SIZE {
+            self.header.flid = crypt::generate_key();
+        }
+
+        self.data.prepare_directory(&self.header.flid);
+
+        writer.write_all(&self.header.to_binary())?;
+        self.data.write_directory(writer)?;
+
+        let key = self.header.flid.clone();
+
+        for i in 0..self.data.entries.len() {
+
+            let encrypt = crypt::is_encrypted(self.data.entries[i].flags);
+
+            if self.data.entries[i].deferred.is_some() {
+                // the entry holds no data, so the clone is cheap
+                let entry = self.data.entries[i].clone();
+                let mut reader = self.entry_reader(&entry)?;
+                write_payload(&mut reader, writer, encrypt, &key, &entry)?;
+            } else {
+                let entry = &self.data.entries[i];
+                write_payload(&mut entry.file_bin.as_slice(), writer, encrypt, &key, entry)?;
+            }
+        }
+
+        Ok(())
     }
 
     /// Extracting all files.
-    pub fn extract_all(&mut self, target_dir: &str) {
+    pub fn extract_all(&mut self, target_dir: &str) -> Result<(), KfnParseError> {
         
         // iterate over all entries
         for i in 0..self.data.entries.len() {
@@ -428,25 +951,29 @@ impl Kfn {
             filename.push_str(&self.data.entries[i].clone().filename.to_string());
         
             // send it to extraction
-            self.extract(self.data.entries[i].clone(), &filename);
+            self.extract(self.data.entries[i].clone(), &filename)?;
         }
+
+        Ok(())
     }
 
     /// Extracting a single file from the entry to a deisgnated output.
-    pub fn extract(&mut self, entry: Entry, output_filename: &str) {
+    /// Entries that are not loaded are streamed from the source.
+    pub fn extract(&mut self, entry: Entry, output_filename: &str) -> Result<(), KfnParseError> {
         
         // set the path and prefix
         let path = std::path::Path::new(&output_filename);
-        let prefix = path.parent().unwrap();
         
         // create directories if they don't exist
-        std::fs::create_dir_all(prefix).unwrap();
+        if let Some(prefix) = path.parent() {
+            std::fs::create_dir_all(prefix)?;
+        }
         
-        let mut output = std::fs::File::create(path).unwrap();
-
-        let buf: Vec<u8> = Vec::from(entry.file_bin);
+        let mut output = std::io::BufWriter::new(std::fs::File::create(path)?);
         
-        std::io::Write::write_all(&mut output, &buf).unwrap();
+        std::io::copy(&mut self.entry_reader(&entry)?, &mut output)?;
+
+        Ok(())
     }
     
 
@@ -456,37 +983,72 @@ impl Kfn {
     //------------------------
 
     /// Helper IO function for reading a byte
-    fn read_byte(&mut self) -> u8 {
+    fn read_byte(&mut self) -> Result<u8, KfnParseError> {
         
-        let result = self.file_data[self.read_head as usize];
+        let mut result = [0u8; 1];
+
+        if self.read_head >= self.source.len() {
+            return Err(KfnParseError::TruncatedData { offset: self.read_head, length: 1 });
+        }
+
+        std::io::Read::read_exact(&mut self.source, &mut result)?;
         
         self.read_head += 1;
         
-        (result & 0xFF).into()
+        Ok(result[0])
     }
 
     /// Helper IO function for reading a dword
-    fn read_dword(&mut self) -> u32 {
+    fn read_dword(&mut self) -> Result<u32, KfnParseError> {
         
-        let b1 = self.read_byte() as u32;
-        let b2 = self.read_byte() as u32;
-        let b3 = self.read_byte() as u32;
-        let b4 = self.read_byte() as u32;
+        let b1 = self.read_byte()? as u32;
+        let b2 = self.read_byte()? as u32;
+        let b3 = self.read_byte()? as u32;
+        let b4 = self.read_byte()? as u32;
 
-        b4 << 24 | b3 << 16 | b2 << 8 | b1
+        Ok(b4 << 24 | b3 << 16 | b2 << 8 | b1)
     }
 
     /// Helper IO function for reading a specified amount fo bytes
-    fn read_bytes(&mut self, length: u32) -> Vec<u8> {
-        
-        let mut array: Vec<u8> = Vec::with_capacity(length as usize);
-        
-        for _ in 0..length {
-        
-            array.push(self.read_byte());
+    fn read_bytes(&mut self, length: u32) -> Result<Vec<u8>, KfnParseError> {
+
+        let length = length as usize;
+
+        // check the length first, so a corrupt length doesn't allocate
+        if self.source.len().saturating_sub(self.read_head) < length {
+            return Err(KfnParseError::TruncatedData { offset: self.read_head, length });
         }
         
-        array
+        let mut array: Vec<u8> = vec![0; length];
+        std::io::Read::read_exact(&mut self.source, &mut array)?;
+
+        self.read_head += length;
+        
+        Ok(array)
     }
 
-}
\ No newline at end of file
+}
+
+/// Writes the plain data of an entry from the reader, encrypting it with the key if needed.
+/// The data has to be exactly as long as the plain length in the directory.
+fn write_payload<R: std::io::Read + ?Sized, W: std::io::Write>(reader: &mut R, writer: &mut W, encrypt: bool, key: &[u8], entry: &Entry) -> Result<(), KfnParseError> {
+
+    let written = if encrypt {
+        let mut encrypt_writer = match crypt::EncryptWriter::new(key, &mut *writer) {
+            Some(encrypt_writer) => encrypt_writer,
+            None => return Err(KfnParseError::InvalidEncryptionKey),
+        };
+        let written = std::io::copy(reader, &mut encrypt_writer)?;
+        encrypt_writer.finish()?;
+        written
+    } else {
+        std::io::copy(reader, writer)?
+    };
+
+    // a shorter payload would shift every following entry
+    if written as usize != entry.len1 {
+        return Err(KfnParseError::TruncatedData { offset: entry.offset, length: entry.len1 });
+    }
+
+    Ok(())
+}
@@ -1,15 +1,15 @@
 #[cfg(test)]
 mod tests {
 
-    use std::{time::{Instant, Duration}};
+    use std::{time::{Instant, Duration}, io::Read};
 
 
-    use crate::{Kfn, kfn_header::KfnHeader, helpers::event::EventType};
+    use crate::{Kfn, KfnParseError, kfn_ini::KfnIni, kfn_ini::trajectory::Trajectory, kfn_ini::eff::{Action, AnimEntry, Effect, TransType, TextEntry}, kfn_builder::{KfnBuilder, LyricLayer}, kfn_header::{KfnHeader, RawTag, TagValue}, kfn_header::tag::{HeaderTag, Genre, Difficulty, KfnType}, helpers::event::EventType, helpers::crypt, helpers::Entry, helpers::file_type::FileType, kfn_render::video::{Audio, AudioTrack, to_yuv420}, kfn_render::preview::Preview, kfn_lyrics::ass::parse_time};
 
     #[test]
     fn file_reading() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
 
         match kfn.parse() {
             Ok(true) => {
@@ -26,58 +26,58 @@ mod tests {
     #[test]
     fn file_writing() {
         
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
         
         kfn.parse().unwrap();
         
-        kfn.export("test/output_write_test.kfn");
+        kfn.export("test/output_write_test.kfn").unwrap();
     }
 
     #[test]
     fn ini_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
 
         kfn.parse().unwrap();
         
-        kfn.data.read_ini();
-        kfn.data.update_ini();
+        kfn.data.read_ini().unwrap();
+        kfn.data.update_ini().unwrap();
         
-        kfn.export("test/output_ini_test.kfn");
+        kfn.export("test/output_ini_test.kfn").unwrap();
     }
 
     #[test]
     fn add_entry_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
 
         kfn.parse().unwrap();
 
-        kfn.add_file("test/art_for_test.jpg");
+        kfn.add_file("test/art_for_test.jpg").unwrap();
 
 
-        kfn.export("test/output_add_test.kfn");
+        kfn.export("test/output_add_test.kfn").unwrap();
     }
 
     #[test]
     fn remove_entry_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
         kfn.parse().unwrap();
 
         //kfn.remove_file("target")
 
-        kfn.export("test/output_remove_test.kfn");
+        kfn.export("test/output_remove_test.kfn").unwrap();
     }
 
     #[test]
     fn extract_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
 
         kfn.parse().unwrap();
 
-        kfn.extract_all("test/extract/");
+        kfn.extract_all("test/extract/").unwrap();
     }
 
     #[test]
@@ -85,37 +85,37 @@ mod tests {
 
         let mut kfn = Kfn::new();
 
-        kfn.add_file("test/insert.mp3");
-        kfn.add_file("test/art_for_test.jpg");
+        kfn.add_file("test/insert.mp3").unwrap();
+        kfn.add_file("test/art_for_test.jpg").unwrap();
 
         kfn.header = KfnHeader::default();
 
         kfn.data.song.populate_from_header(&kfn.header);
 
-        kfn.set_source("insert.mp3");
+        kfn.set_source("insert.mp3").unwrap();
 
-        kfn.export("test/new_output.kfn");
+        kfn.export("test/new_output.kfn").unwrap();
     }
 
     #[test]
     fn read_anims_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
         
         kfn.parse().unwrap();
 
-        kfn.data.song.load_eff();
+        kfn.data.song.load_eff().unwrap();
 
     }
 
     #[test]
     fn create_test_read_anims() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
         
         kfn.parse().unwrap();
 
-        kfn.data.song.load_eff();
+        kfn.data.song.load_eff().unwrap();
         
         kfn.data.song.ini.clear();
 
@@ -124,26 +124,26 @@ mod tests {
         //kfn.add_file("test/insert.mp3");
 
         kfn.data.song.set_eff();
-        kfn.data.update_ini();
+        kfn.data.update_ini().unwrap();
 
-        kfn.extract(kfn.data.get_entry_by_name("Song.ini").unwrap(), "test/new_Song.ini");
+        kfn.extract(kfn.data.get_entry_by_name("Song.ini").unwrap(), "test/new_Song.ini").unwrap();
 
-        kfn.export("test/new_output_ini.kfn");
+        kfn.export("test/new_output_ini.kfn").unwrap();
     }
 
     #[test]
     #[ignore]
     fn playback_test() {
 
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
     
         kfn.parse().unwrap();
 
-        kfn.data.song.load_eff();
+        kfn.data.song.load_eff().unwrap();
 
         //kfn.get_texts_and_syncs();
 
-        let (sender_caller, receiver_caller) = kfn.play();
+        let (sender_caller, receiver_caller) = kfn.play().unwrap();
         //sender_caller.send("END".to_string()).unwrap();
         let now = Instant::now();
 
@@ -170,18 +170,1025 @@ mod tests {
     #[test]
     
     fn playback_video_test() {
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
     
         kfn.parse().unwrap();
 
-        kfn.data.song.load_eff();
+        kfn.data.song.load_eff().unwrap();
 
-        kfn.play_kfn();
+        kfn.play_kfn().unwrap();
+    }
+
+    #[test]
+    fn decrypt_test() {
+        // FIPS-197 AES-128 test vector
+        let key: Vec<u8> = (0..16).collect();
+        let stored = vec![
+            0x69, 0xc4, 0xe0, 0xd8, 0x6a, 0x7b, 0x04, 0x30,
+            0xd8, 0xcd, 0xb7, 0x80, 0x70, 0xb4, 0xc5, 0x5a,
+        ];
+
+        let plain = crypt::decrypt(&key, &stored, 10).unwrap();
+
+        assert_eq!(plain, vec![0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99]);
+        assert!(crypt::decrypt(b"short", &stored, 10).is_none());
+    }
+
+    #[test]
+    fn encrypt_test() {
+
+        let mut kfn = Kfn::new();
+        let music: Vec<u8> = (0..100).collect();
+
+        kfn.data.add_entry(test_entry("Song.ini", FileType::SongIni, b"[General]\r\nEffectCount=0\r\n".to_vec()));
+        kfn.data.add_entry(test_entry("song.mp3", FileType::Music, music.clone()));
+        kfn.set_encrypted("song.mp3", true);
+
+        let path = std::env::temp_dir().join("kfn_rs_encrypt_test.kfn");
+        kfn.export(path.to_str().unwrap()).unwrap();
+
+        let mut reader = std::fs::File::open(path).unwrap();
+        let mut parsed = Kfn::from_reader(&mut reader).unwrap();
+        parsed.parse().unwrap();
+
+        let entry = parsed.data.get_entry_by_name("song.mp3").unwrap();
+        assert!(crypt::is_encrypted(entry.flags));
+        assert_eq!(entry.len1, 100);
+        assert_eq!(entry.len2, 112);
+        assert_eq!(entry.file_bin, music);
+        assert_eq!(parsed.header.flid, kfn.header.flid);
+    }
+
+    #[test]
+    fn truncated_file_test() {
+
+        let mut kfn = Kfn::from_bytes(b"KFNBDIFM\x01\x03\x00".to_vec());
+
+        match kfn.parse() {
+            Err(KfnParseError::TruncatedData { offset: 11, length: 1 }) => (),
+            other => panic!("Expected truncated data, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn malformed_sync_test() {
+
+        let mut kfn = Kfn::new();
+        kfn.data.add_entry(test_entry(
+            "Song.ini", 
+            FileType::SongIni, 
+            b"[General]\r\nEffectCount=1\r\n[Eff1]\r\nID=1\r\nNbAnim=0\r\nSync0=100,1x0\r\n".to_vec()
+        ));
+        kfn.data.read_ini().unwrap();
+
+        match kfn.data.song.load_eff() {
+            Err(KfnParseError::InvalidIniValue { section, key, value }) => {
+                assert_eq!((section.as_str(), key.as_str(), value.as_str()), ("Eff1", "Sync0", "1x0"));
+            },
+            other => panic!("Expected invalid value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lazy_test() {
+
+        let mut kfn = Kfn::new();
+        let music: Vec<u8> = (0..100).collect();
+        let image: Vec<u8> = (0..50).rev().collect();
+
+        kfn.data.add_entry(test_entry("Song.ini", FileType::SongIni, b"[General]\r\nEffectCount=0\r\n".to_vec()));
+        kfn.data.add_entry(test_entry("song.mp3", FileType::Music, music.clone()));
+        kfn.data.add_entry(test_entry("bg.jpg", FileType::Image, image.clone()));
+        kfn.set_encrypted("song.mp3", true);
+
+        let path = std::env::temp_dir().join("kfn_rs_lazy_test.kfn");
+        kfn.export(path.to_str().unwrap()).unwrap();
+
+        let mut lazy = Kfn::open_lazy(path.to_str().unwrap()).unwrap();
+        lazy.parse().unwrap();
+
+        // only the Song.ini is loaded
+        assert!(lazy.data.get_entry_by_name("Song.ini").unwrap().deferred.is_none());
+        assert!(lazy.data.get_entry_by_name("song.mp3").unwrap().deferred.is_some());
+        assert!(lazy.data.get_entry_by_name("song.mp3").unwrap().file_bin.is_empty());
+
+        assert_eq!(lazy.read_entry("song.mp3").unwrap(), music);
+
+        let entry = lazy.data.get_entry_by_name("bg.jpg").unwrap();
+        let mut streamed: Vec<u8> = Vec::new();
+        lazy.entry_reader(&entry).unwrap().read_to_end(&mut streamed).unwrap();
+        assert_eq!(streamed, image);
+
+        lazy.load_entry("bg.jpg").unwrap();
+        assert_eq!(lazy.data.get_entry_by_name("bg.jpg").unwrap().file_bin, image);
+    }
+
+    #[test]
+    fn stream_export_test() {
+
+        let mut kfn = Kfn::new();
+        let music: Vec<u8> = (0..100).collect();
+
+        kfn.data.add_entry(test_entry("Song.ini", FileType::SongIni, b"[General]\r\nEffectCount=0\r\n".to_vec()));
+        kfn.data.add_entry(test_entry("song.mp3", FileType::Music, music.clone()));
+        kfn.set_encrypted("song.mp3", true);
+
+        let path = std::env::temp_dir().join("kfn_rs_stream_test.kfn");
+        kfn.export(path.to_str().unwrap()).unwrap();
+
+        // the payloads of the lazily read file are streamed straight from the source
+        let mut lazy = Kfn::open_lazy(path.to_str().unwrap()).unwrap();
+        lazy.parse().unwrap();
+        lazy.set_encrypted("song.mp3", false);
+
+        let mut output: Vec<u8> = Vec::new();
+        lazy.write_to(&mut output).unwrap();
+
+        let mut streamed = Kfn::from_bytes(output);
+        streamed.parse().unwrap();
+        let entry = streamed.data.get_entry_by_name("song.mp3").unwrap();
+        assert_eq!(entry.flags, 0);
+        assert_eq!(entry.file_bin, music);
+
+        // parsing again reads the same entries
+        let entries = streamed.data.entries.clone();
+        streamed.parse().unwrap();
+        assert_eq!(streamed.data.entries, entries);
+
+        // the lazy file can still be read after writing
+        assert_eq!(lazy.read_entry("song.mp3").unwrap(), music);
+
+        // the directory and the files are only written in memory, if every entry is loaded
+        let mut output: Vec<u8> = Vec::new();
+        lazy.write_to(&mut output).unwrap();
+        assert!(matches!(lazy.data.to_binary_with_key(&[]), Err(KfnParseError::EntryNotLoaded(name)) if name == "song.mp3"));
+        lazy.load_all().unwrap();
+        let data = lazy.data.to_binary_with_key(&[]).unwrap();
+        assert!(output.ends_with(&data));
+    }
+
+    #[test]
+    fn round_trip_test() {
+
+        let key: Vec<u8> = (0..16).collect();
+        let ini = b"[General]\r\nTitle=Song\r\nEffectCount=0\r\n\r\n[Materials]\r\nMatCount=1\r\nMat0=song.mp3\r\n".to_vec();
+        let music: Vec<u8> = (0..100).collect();
+        let stored_music = crypt::encrypt(&key, &music).unwrap();
+
+        // header with an unusual order, an unknown dword and data tag, a comment and a custom end value
+        let mut file: Vec<u8> = b"KFNB".to_vec();
+        let mut tag = |signature: &str, l_type: u8, value: u32, data: &[u8]| {
+            file.extend_from_slice(signature.as_bytes());
+            file.push(l_type);
+            file.extend_from_slice(&value.to_le_bytes());
+            file.extend_from_slice(data);
+        };
+        tag("TITL", 2, 4, b"Song");
+        tag("XTRA", 1, 7, b"");
+        tag("COMM", 2, 2, b"Hi");
+        tag("DIFM", 1, 3, b"");
+        tag("FLID", 2, 16, &key);
+        tag("XDAT", 2, 3, b"abc");
+        tag("ENDH", 1, 0, b"");
+
+        // Song.ini first, followed by the encrypted music
+        file.extend_from_slice(&2u32.to_le_bytes());
+        for (name, file_type, len1, offset, len2, flags) in [
+            ("Song.ini", 1u32, ini.len(), 0, ini.len(), 0u32),
+            ("song.mp3", 2u32, music.len(), ini.len(), stored_music.len(), 1u32),
+        ] {
+            file.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            file.extend_from_slice(name.as_bytes());
+            for value in [file_type, len1 as u32, offset as u32, len2 as u32, flags] {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        file.extend_from_slice(&ini);
+        file.extend_from_slice(&stored_music);
+
+        let mut kfn = Kfn::from_bytes(file.clone());
+        kfn.parse().unwrap();
+        assert_eq!(kfn.header.comment, "Hi");
+        assert_eq!(kfn.data.get_entry_by_name("song.mp3").unwrap().file_bin, music);
+
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+        assert_eq!(output, file);
+
+        // the same goes for lazily read files
+        let mut lazy = Kfn::from_reader_lazy(std::io::Cursor::new(file.clone())).unwrap();
+        lazy.parse().unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        lazy.write_to(&mut output).unwrap();
+        assert_eq!(output, file);
+
+        // editing keeps the unknown tags and the place of the Song.ini
+        kfn.header.artist = "Artist".to_string();
+        kfn.update().unwrap();
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+
+        let mut edited = Kfn::from_bytes(output);
+        edited.parse().unwrap();
+        let signatures: Vec<&str> = edited.header.tags.iter().map(|tag| tag.signature.as_str()).collect();
+        assert_eq!(signatures, ["TITL", "XTRA", "COMM", "DIFM", "FLID", "XDAT", "ARTS", "ENDH"]);
+        assert_eq!(edited.header.artist, "Artist");
+        assert_eq!(edited.data.entries[0].filename, "Song.ini");
+        assert_eq!(edited.data.get_entry_by_name("song.mp3").unwrap().file_bin, music);
+    }
+
+    #[test]
+    fn header_tags_test() {
+
+        let mut kfn = Kfn::new();
+        kfn.data.add_entry(test_entry("Song.ini", FileType::SongIni, b"[General]\r\nEffectCount=0\r\n".to_vec()));
+        kfn.header.set_tag(HeaderTag::Genre(Genre::Id(12)));
+        kfn.header.set_tag(HeaderTag::DiffMen(Difficulty::new(4)));
+        kfn.header.set_tag(HeaderTag::Title("Song".to_string()));
+        kfn.header.set_tag(HeaderTag::Unknown(RawTag { signature: "XTRA".to_string(), value: TagValue::Data(vec![0xff, 0]) }));
+        assert_eq!(Difficulty::new(6), None);
+
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+
+        let mut read = Kfn::from_bytes(output);
+        read.parse().unwrap();
+
+        assert_eq!(read.header.get_tag("GNRE"), Some(HeaderTag::Genre(Genre::Id(12))));
+        assert_eq!(read.header.get_tag("DIFM"), Some(HeaderTag::DiffMen(Difficulty::new(4))));
+        assert_eq!(read.header.get_tag("DIFW"), Some(HeaderTag::DiffWomen(Difficulty::new(1))));
+        assert_eq!(read.header.get_tag("TYPE"), Some(HeaderTag::KfnType(KfnType::Standard)));
+        assert_eq!(read.header.get_tag("TITL"), Some(HeaderTag::Title("Song".to_string())));
+        assert_eq!(read.header.get_tag("XTRA"), Some(HeaderTag::Unknown(RawTag { 
+            signature: "XTRA".to_string(), value: TagValue::Data(vec![0xff, 0]) 
+        })));
+
+        // setting a tag on a read file keeps the order, new tags go before the end
+        read.header.set_tag(HeaderTag::Genre(Genre::None));
+        read.header.set_tag(HeaderTag::Unknown(RawTag { signature: "XNEW".to_string(), value: TagValue::Dword(1) }));
+        let signatures: Vec<String> = read.header.header_tags().iter().map(|tag| tag.signature().to_string()).collect();
+        assert_eq!(&signatures[..3], ["DIFM", "DIFW", "GNRE"]);
+        assert_eq!(&signatures[signatures.len() - 3..], ["XTRA", "XNEW", "ENDH"]);
+        assert_eq!(read.header.genre, u32::MAX);
+        assert_eq!(Genre::None.to_string(), "-1");
+
+        // a new file has no genre, and valid difficulties
+        let mut kfn = Kfn::new();
+        kfn.data.add_entry(test_entry("Song.ini", FileType::SongIni, b"[General]\r\nEffectCount=0\r\n".to_vec()));
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+
+        let mut fresh = Kfn::from_bytes(output);
+        fresh.parse().unwrap();
+        assert_eq!(fresh.header.get_tag("GNRE"), Some(HeaderTag::Genre(Genre::None)));
+        assert_eq!(fresh.header.get_tag("DIFM"), Some(HeaderTag::DiffMen(Difficulty::new(1))));
+    }
+
+    #[test]
+    fn builder_test() {
+
+        let music: Vec<u8> = (0..100).collect();
+
+        let mut kfn = KfnBuilder::new()
+            .title("Song")
+            .artist("Artist")
+            .genre(Genre::Id(3))
+            .difficulty(Difficulty::new(2).unwrap(), Difficulty::new(3).unwrap())
+            .source("song.mp3", music.clone())
+            .vocal_track("vocal.mp3", vec![1, 2, 3], true)
+            .background(500, "bg2.jpg", vec![5, 6])
+            .background(0, "bg1.jpg", vec![4])
+            .font("font.ttf", vec![7])
+            .lyrics(LyricLayer::new()
+                .line(vec![(100, "Hel"), (120, "lo "), (150, "world")])
+                .line(vec![(300, "Sec"), (320, "ond")])
+                .font("font.ttf", 20)
+                .colors("#FFFFFFFF", "#000000FF"))
+            .lyrics(LyricLayer::new().line(vec![(200, "Backing")]))
+            .encrypt("song.mp3")
+            .build()
+            .unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+
+        let mut read = Kfn::from_bytes(output);
+        read.parse().unwrap();
+
+        assert_eq!(read.header.title, "Song");
+        assert_eq!(read.header.genre, 3);
+        assert_eq!(read.data.song.get_source_name().unwrap(), "song.mp3");
+        assert_eq!(read.data.song.get_secondary_source().unwrap(), "vocal.mp3");
+        assert!(read.data.song.replaces_track());
+        assert_eq!(read.data.get_entry_by_name("song.mp3").unwrap().file_bin, music);
+        assert_eq!(read.data.song.ini.get_from(Some("Materials"), "MatCount"), Some("5"));
+
+        let effs = &read.data.song.effs;
+        assert_eq!(effs.len(), 3);
+        assert_eq!(effs[0].id, 51);
+        assert_eq!(effs[0].initial_lib_image.as_deref(), Some("bg1.jpg"));
+        assert_eq!(effs[0].anims[1].time, 500);
+
+        assert_eq!(effs[1].id, 1);
+        assert_eq!(effs[1].initial_font, Some(("font.ttf".to_string(), 20)));
+        assert_eq!(effs[1].syncs, vec![100, 120, 150, 300, 320]);
+        assert_eq!(effs[1].texts[0].display, "Hello world");
+        assert_eq!(effs[1].texts[0].fragments, vec![(100, "Hel".to_string()), (120, "lo ".to_string()), (150, "world".to_string())]);
+        assert_eq!(effs[2].texts[0].fragments, vec![(200, "Backing".to_string())]);
+
+        // fragments that would split differently are rejected
+        let invalid = KfnBuilder::new()
+            .source("song.mp3", music)
+            .lyrics(LyricLayer::new().line(vec![(0, "two words")]))
+            .build();
+        assert!(matches!(invalid, Err(KfnParseError::InvalidIniValue { .. })));
+    }
+
+    #[test]
+    fn ini_model_test() {
+
+        let syncs: Vec<String> = (1..=12).map(|n| (n * 10).to_string()).collect();
+        let text = format!("[General]\r\nTitle=Song\r\nGenreID=-1\r\nSource=1,I,song.mp3\r\nEffectCount=2\r\nDiffMen=\r\nCustom=1\r\n\
+            [Materials]\r\nMatCount=2\r\nMat0=song.mp3\r\nMat1=bg.jpg\r\n\
+            [MP3Music]\r\nNumTracks=1\r\nTrack0=vocal.mp3,0,1\r\n\
+            [Eff1]\r\nID=51\r\nNbAnim=2\r\nLibImage=bg.jpg\r\nLocked=0\r\n\
+            Anim0=0|ChgBgImg:LibImage=bg.jpg,Effect=AlphaBlending,TransitionTime=1.5,TransitionType=Smooth\r\n\
+            Anim1=100|ChgColImageColor:ImageColor=#FF0000FF|ChgFloatDepth:Depth=0.5|ChgFloatZoom:Zoom=big\r\n\
+            [Eff2]\r\nID=1\r\nNbAnim=0\r\nTextCount=4\r\nTrajectory=PlainTopToBottom*2*1*1*1\r\nFont=Arial*12\r\nInSync=1\r\n\
+            Sync0={}\r\nSync1={}\r\nText0=Hel/lo world\r\nText1=\r\nText2=a b c d\r\nText3=e/f g h i\r\n\
+            [Other]\r\nKey=Value\r\n", syncs[..10].join(","), syncs[10..].join(","));
+
+        let mut song = KfnIni::new();
+        song.ini = ini::Ini::load_from_str(&text).unwrap();
+        song.load().unwrap();
+
+        assert_eq!(song.general.extra, vec![("Custom".to_string(), "1".to_string())]);
+        assert_eq!(song.general.diff_men, None);
+        assert_eq!(song.materials, vec!["song.mp3", "bg.jpg"]);
+        assert!(song.replaces_track());
+        assert_eq!(song.effs[0].anims[1].anim_entries[1].action, Action::ChgFloatDepth(0.5));
+        assert_eq!(song.effs[0].anims[1].anim_entries[2].action, Action::Unknown("ChgFloatZoom:Zoom".to_string(), "big".to_string()));
+        assert_eq!(song.effs[1].syncs.len(), 12);
+        assert_eq!(song.effs[1].texts.len(), 4);
+        assert_eq!(song.effs[1].extra, vec![("InSync".to_string(), "1".to_string())]);
+
+        // editing a line keeps everything else
+        song.effs[1].texts[0].fragments[2].1 = "there".to_string();
+        song.effs[1].texts[0].display = "Hello there".to_string();
+
+        let original = song.clone();
+        song.write_ini();
+
+        let mut written: Vec<u8> = Vec::new();
+        song.ini.write_to(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+
+        assert!(written.contains("Anim0=0|ChgBgImg:LibImage=bg.jpg,Effect=AlphaBlending,TransitionTime=1.5,TransitionType=Smooth"));
+        assert!(written.contains("Anim1=100|ChgColImageColor:ImageColor=#FF0000FF|ChgFloatDepth:Depth=0.5|ChgFloatZoom:Zoom=big"));
+        assert!(written.contains("Sync1=110,120"));
+        assert!(written.contains("Text0=Hel/lo there"));
+        assert!(written.contains("Key=Value"));
+
+        let mut read = KfnIni::new();
+        read.ini = ini::Ini::load_from_str(&written).unwrap();
+        read.load().unwrap();
+
+        assert_eq!(read.general, original.general);
+        assert_eq!(read.materials, original.materials);
+        assert_eq!(read.mp3_music, original.mp3_music);
+        assert_eq!(read.effs, original.effs);
+
+        // trajectories the model doesn't know, and empty ones, are written back as they are
+        for value in ["Spiral*2*1*1*1", "Spiral", ""] {
+            assert_eq!(value.parse::<Trajectory>().unwrap().to_string(), value);
+        }
+        assert_eq!("".parse::<Trajectory>().unwrap(), Trajectory::None);
+        assert!(matches!("Still*2*1".parse::<Trajectory>(), Err(KfnParseError::InvalidTrajectory(_))));
+    }
+
+    #[test]
+    fn anim_vocabulary_test() {
+
+        let lines = [
+            "ChgColActiveColor:ActiveColor=#FFFF00FF,Effect=ZoomIn,TransitionTime=2,TransitionType=Bounce5",
+            "ChgColOutlineColor:OutlineColor=#000000FF",
+            "ChgFloatBlur:Blur=1.5",
+            "ChgFont:Font=Arial.ttf*24",
+            "ChgTrajectory:Trajectory=PlainTopToBottom*2*1*1*1",
+            "ChgSomething:Whatever=1,Effect=Spin,TransitionTime=1,TransitionType=Wobbly,Layer=2",
+            "ChgBgImg:LibImage=bg.jpg,Effect=AlphaBlending",
+            "ChgFloatAlpha:Alpha=0.5,TransitionTime=0",
+        ];
+
+        let mut entries: Vec<AnimEntry> = Vec::new();
+        for line in lines {
+            let (action, params) = line.split_once(',').unwrap_or((line, ""));
+            let mut entry = AnimEntry { action: action.parse::<Action>().unwrap(), ..Default::default() };
+            for (key, value) in params.split(',').filter(|p| !p.is_empty()).map(|p| p.split_once('=').unwrap()) {
+                match key {
+                    "Effect" => entry.effect = Some(Effect::from(value)),
+                    "TransitionTime" => entry.trans_time = Some(value.parse().unwrap()),
+                    "TransitionType" => entry.trans_type = TransType::from(value),
+                    _ => entry.extra.push((key.to_string(), value.to_string())),
+                }
+            }
+            assert_eq!(entry.to_string(), line);
+            entries.push(entry);
+        }
+
+        assert_eq!(entries[0].action, Action::ChgColActiveColor("#FFFF00FF".to_string()));
+        assert_eq!(entries[0].effect, Some(Effect::ZoomIn));
+        assert_eq!(entries[1].action, Action::ChgCol("OutlineColor".to_string(), "#000000FF".to_string()));
+        assert_eq!(entries[2].action, Action::ChgFloat("Blur".to_string(), 1.5));
+        assert_eq!(entries[3].action, Action::ChgFont("Arial.ttf".to_string(), 24));
+        assert_eq!(entries[5].action, Action::Unknown("ChgSomething:Whatever".to_string(), "1".to_string()));
+        assert_eq!(entries[5].effect, Some(Effect::Unknown("Spin".to_string())));
+        assert_eq!(entries[5].trans_type, TransType::Unknown("Wobbly".to_string()));
+
+        // a property that doesn't belong to the action is kept as it is
+        assert!(matches!("ChgBgImg:Image=bg.jpg".parse::<Action>(), Ok(Action::Unknown(_, _))));
+        assert!(matches!("ChgFloatDepth:Depth=deep".parse::<Action>(), Err(KfnParseError::InvalidAction(_))));
+        assert!(matches!("ChgBgImg".parse::<Action>(), Err(KfnParseError::InvalidAction(_))));
+    }
+
+    /// Builds a small song with two text layers for the lyrics conversion tests.
+    fn lyrics_kfn() -> Kfn {
+        KfnBuilder::new()
+            .title("Song")
+            .artist("Artist")
+            .album("Album")
+            .source("song.mp3", vec![0; 10])
+            .background(0, "bg.jpg", vec![1])
+            .lyrics(LyricLayer::new()
+                .line(vec![(100, "Hel"), (120, "lo "), (150, "world")])
+                .line(vec![(6150, "Sec"), (6320, "ond")])
+                .font("font.ttf", 20)
+                .colors("#FFFF00FF", "#FFFFFFFF"))
+            .lyrics(LyricLayer::new().line(vec![(200, "Backing")]))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn lrc_export_test() {
+
+        let kfn = lyrics_kfn();
+
+        assert_eq!(kfn.to_lrc(false), "[ti:Song]\n[ar:Artist]\n[al:Album]\n\
+            [00:01.00]Hello world\n[00:02.00]Backing\n[01:01.50]Second\n");
+        assert_eq!(kfn.to_lrc(true), "[ti:Song]\n[ar:Artist]\n[al:Album]\n\
+            [00:01.00]<00:01.00>Hel<00:01.20>lo <00:01.50>world\n[00:02.00]<00:02.00>Backing\n\
+            [01:01.50]<01:01.50>Sec<01:03.20>ond\n");
+    }
+
+    #[test]
+    fn lrc_import_test() {
+
+        let lrc = "[ti:Song]\n[ar:Artist]\n[by:someone]\n\
+            [00:02.00]<00:02.00>Sec<00:02.5>ond <00:03.000>line\n\
+            [00:01.00]First  line\n\
+            [00:04.00][00:10.00]Ch<00:04.50>orus\n\
+            [00:05.00]\n\
+            no time\n";
+
+        let mut kfn = Kfn::new();
+        kfn.import_lrc(lrc).unwrap();
+
+        assert_eq!(kfn.header.title, "Song");
+        assert_eq!(kfn.header.artist, "Artist");
+
+        let eff = &kfn.data.song.effs[0];
+        assert_eq!(eff.id, 1);
+        assert_eq!(eff.texts.len(), 5);
+        assert_eq!(eff.texts[0].fragments, vec![(100, "First ".to_string()), (100, "line".to_string())]);
+        assert_eq!(eff.texts[1].fragments, vec![(200, "Sec".to_string()), (250, "ond ".to_string()), (300, "line".to_string())]);
+        assert_eq!(eff.texts[2].fragments, vec![(400, "Ch".to_string()), (450, "orus".to_string())]);
+        assert!(eff.texts[3].fragments.is_empty());
+        assert_eq!(eff.texts[4].fragments, vec![(1000, "Ch".to_string()), (1050, "orus".to_string())]);
+        assert_eq!(eff.syncs, vec![100, 100, 200, 250, 300, 400, 450, 1000, 1050]);
+
+        // the written Song.ini reads back to the same timing
+        let mut output: Vec<u8> = Vec::new();
+        kfn.write_to(&mut output).unwrap();
+
+        let mut read = Kfn::from_bytes(output);
+        read.parse().unwrap();
+
+        assert_eq!(read.header.title, "Song");
+        assert_eq!(read.data.song.effs, kfn.data.song.effs);
+        assert_eq!(read.to_lrc(true), kfn.to_lrc(true));
+
+        // the offset moves the lyrics earlier
+        let mut kfn = Kfn::new();
+        kfn.import_lrc("[offset:500]\n[00:01.00]Line").unwrap();
+        assert_eq!(kfn.data.song.effs[0].syncs, vec![50]);
+
+        assert!(matches!(Kfn::new().import_lrc("[00:01.00]<00:0x>Bad"), Err(KfnParseError::InvalidLyrics { line: 1, .. })));
+    }
+
+    #[test]
+    fn ass_export_test() {
+
+        let ass = lyrics_kfn().to_ass();
+
+        assert!(ass.starts_with("[Script Info]\nTitle: Song\n"));
+        assert!(ass.contains("Style: Eff2,font,20,&H0000FFFF,&H00FFFFFF,"));
+        assert!(ass.contains("Style: Eff3,Arial,40,&H0000FFFF,&H00FFFFFF,"));
+        assert!(ass.contains("Dialogue: 0,0:00:01.00,0:01:01.50,Eff2,,0,0,0,karaoke,{\\k20}Hel{\\k30}lo {\\k100}world\n"));
+        assert!(ass.contains("Dialogue: 0,0:01:01.50,0:01:04.20,Eff2,,0,0,0,karaoke,{\\k170}Sec{\\k100}ond\n"));
+        assert!(ass.contains("Dialogue: 1,0:00:02.00,0:00:03.00,Eff3,,0,0,0,karaoke,{\\k100}Backing\n"));
+    }
+
+    #[test]
+    fn ass_import_test() {
+
+        // the exported script reads back to the same lyrics
+        let kfn = lyrics_kfn();
+        let mut imported = Kfn::new();
+        imported.import_ass(&kfn.to_ass()).unwrap();
+
+        assert_eq!(imported.header.title, "Song");
+        let effs = &imported.data.song.effs;
+        assert_eq!(effs.len(), 2);
+        assert_eq!(effs[0].texts, kfn.data.song.effs[1].texts.iter().map(|text| TextEntry { eff_num: 1, ..text.clone() }).collect::<Vec<TextEntry>>());
+        assert_eq!(effs[0].syncs, kfn.data.song.effs[1].syncs);
+        assert_eq!(effs[0].initial_font, Some(("font".to_string(), 20)));
+        assert_eq!(effs[0].initial_active_color.as_deref(), Some("#FFFF00FF"));
+        assert_eq!(effs[0].initial_inactive_color.as_deref(), Some("#FFFFFFFF"));
+        assert_eq!(effs[1].syncs, vec![200]);
+
+        let ssa = "[Script Info]\nTitle: Other\n\n[V4 Styles]\n\
+            Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, TertiaryColour, BackColour\n\
+            Style: Default,Verdana,32.4,255,16777215,0,0\n\n[Events]\n\
+            Format: Marked, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+            Comment: Marked=0,0:00:00.00,0:00:01.00,Default,,0,0,0,,ignored\n\
+            Dialogue: Marked=0,0:00:01.50,0:00:04.00,Default,,0,0,0,,{\\kf50\\b1}Ka{\\ko25}ra,{\\K25} oke\\Nline\n";
+
+        let mut imported = Kfn::new();
+        imported.import_ass(ssa).unwrap();
+
+        let eff = &imported.data.song.effs[0];
+        assert_eq!(imported.header.title, "Other");
+        assert_eq!(eff.initial_font, Some(("Verdana".to_string(), 32)));
+        assert_eq!(eff.initial_active_color.as_deref(), Some("#FF0000FF"));
+        assert_eq!(eff.texts[0].fragments, vec![(150, "Ka".to_string()), (200, "ra, ".to_string()), (225, "oke ".to_string()), (225, "line".to_string())]);
+
+        assert!(matches!(Kfn::new().import_ass("[Events]\nFormat: Start, Text\nDialogue: 0:00,x"), Err(KfnParseError::InvalidLyrics { line: 3, .. })));
+
+        // fractions of other lengths are still centiseconds
+        assert_eq!(parse_time("0:00:01.5"), Some(150));
+        assert_eq!(parse_time("0:00:01.500"), Some(150));
+        assert_eq!(parse_time("0:00:01.25"), Some(125));
+        assert_eq!(parse_time("0:00:01"), Some(100));
+        assert_eq!(parse_time("0:00:01.-5"), None);
+    }
+
+    #[test]
+    fn ultrastar_test() {
+
+        let kfn = lyrics_kfn();
+        let txt = kfn.to_ultrastar(300.0);
+
+        assert_eq!(txt, "#TITLE:Song\n#ARTIST:Artist\n#MP3:song.mp3\n#BPM:300\n#GAP:1000\n\
+            : 0 4 0 Hel\n: 4 6 0 lo \n: 10 20 0 world\n- 30\n: 1210 34 0 Sec\n: 1244 20 0 ond\nE\n");
+
+        // a parsed header keeps the prefix of the source
+        let mut parsed = lyrics_kfn();
+        parsed.header.source_file = "1,I,song.mp3".to_string();
+        assert!(parsed.to_ultrastar(300.0).contains("#MP3:song.mp3\n"));
+
+        // the exported song reads back to the same timing
+        let mut imported = Kfn::new();
+        imported.import_ultrastar(&txt).unwrap();
+
+        assert_eq!(imported.header.title, "Song");
+        assert_eq!(imported.data.song.effs.len(), 1);
+        assert_eq!(imported.data.song.effs[0].syncs, kfn.data.song.effs[1].syncs);
+        assert_eq!(imported.data.song.effs[0].texts[0].display, "Hello world");
+
+        let duet = "#TITLE:Duet\n#BPM:150,0\n#GAP:500\n#RELATIVE:yes\n\
+            P1\n: 0 2 5 Sing~\n* 2 2 7  along\n- 4 8\nF 0 2 3 now\nP2\n: 12 4 0 Back\nE\n: 99 1 0 ignored\n";
+
+        let mut imported = Kfn::new();
+        imported.import_ultrastar(duet).unwrap();
+
+        let effs = &imported.data.song.effs;
+        assert_eq!(effs.len(), 2);
+        assert_eq!(effs[0].texts[0].fragments, vec![(50, "Sing ".to_string()), (70, "along".to_string())]);
+        assert_eq!(effs[0].texts[1].fragments, vec![(130, "now".to_string())]);
+        assert_eq!(effs[1].texts[0].fragments, vec![(170, "Back".to_string())]);
+
+        assert!(matches!(Kfn::new().import_ultrastar(": 0 1 0 no bpm"), Err(KfnParseError::InvalidLyrics { line: 1, .. })));
+    }
+
+    #[test]
+    fn kar_import_test() {
+
+        use midly::{Smf, Header, Format, Timing, TrackEvent, TrackEventKind, MetaMessage};
+
+        let meta = |delta: u32, message: MetaMessage<'static>| TrackEvent { delta: delta.into(), kind: TrackEventKind::Meta(message) };
+
+        let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(480.into())));
+        smf.tracks.push(vec![
+            meta(0, MetaMessage::Tempo(500_000.into())),
+            meta(960, MetaMessage::Tempo(250_000.into())),
+            meta(0, MetaMessage::EndOfTrack),
+        ]);
+        smf.tracks.push(vec![
+            meta(0, MetaMessage::Text(b"@KMIDI KARAOKE FILE")),
+            // a marker starting with a character decoded from Latin-1 into two bytes
+            meta(0, MetaMessage::Text(b"@\xe9t\xe9")),
+            meta(0, MetaMessage::Text(b"@LENGL")),
+            meta(0, MetaMessage::Text(b"@TSong")),
+            meta(0, MetaMessage::Text(b"@TArtist")),
+            meta(480, MetaMessage::Text(b"\\Hel")),
+            meta(240, MetaMessage::Text(b"lo ")),
+            meta(240, MetaMessage::Text(b"world")),
+            meta(480, MetaMessage::Text(b"/Sec")),
+            meta(240, MetaMessage::Text(b"ond")),
+            meta(0, MetaMessage::Lyric(b"ignored")),
+            meta(240, MetaMessage::Text(b"\\Third\xe9")),
+            meta(0, MetaMessage::EndOfTrack),
+        ]);
+
+        let mut data: Vec<u8> = Vec::new();
+        smf.write_std(&mut data).unwrap();
+
+        let mut kfn = Kfn::new();
+        kfn.import_kar(&data).unwrap();
+
+        assert_eq!(kfn.header.title, "Song");
+        assert_eq!(kfn.header.artist, "Artist");
+        assert_eq!(kfn.header.language, "ENGL");
+
+        // 500 ms for the first 960 ticks, 250 ms for every 480 after
+        let eff = &kfn.data.song.effs[0];
+        assert_eq!(eff.texts.len(), 4);
+        assert_eq!(eff.texts[0].fragments, vec![(50, "Hel".to_string()), (75, "lo ".to_string()), (100, "world".to_string())]);
+        assert_eq!(eff.texts[1].fragments, vec![(125, "Sec".to_string()), (138, "ond".to_string())]);
+        assert!(eff.texts[2].fragments.is_empty());
+        assert_eq!(eff.texts[3].fragments, vec![(150, "Third\u{e9}".to_string())]);
+
+        assert!(matches!(Kfn::new().import_kar(b"MThd"), Err(KfnParseError::InvalidMidi(_))));
+    }
+
+    #[test]
+    fn cdg_test() {
+
+        let mut kfn = lyrics_kfn();
+        let cdg = kfn.to_cdg().unwrap();
+        assert_eq!(cdg.len() % 24, 0);
+
+        // decoding the stream up to a time, into the palette indices of the screen
+        let screen_at = |time: usize| {
+            let mut pixels = vec![0u8; 300 * 216];
+            for packet in cdg.chunks(24).take(time * 3).filter(|packet| packet[0] & 0x3F == 9) {
+                let data = &packet[4..20];
+                let xor = match packet[1] & 0x3F {
+                    6 => false,
+                    38 => true,
+                    _ => continue,
+                };
+                let (row, column) = (data[2] as usize, data[3] as usize);
+                for y in 0..12 {
+                    for x in 0..6 {
+                        let color = if data[4 + y] & (0x20 >> x) != 0 { data[1] } else { data[0] };
+                        let pixel = &mut pixels[(row * 12 + y) * 300 + column * 6 + x];
+                        *pixel = if xor { *pixel ^ color } else { color };
+                    }
+                }
+            }
+            pixels
+        };
+        let count = |pixels: &Vec<u8>, color: u8| pixels.iter().filter(|pixel| **pixel == color).count();
+
+        // the first line is shown before it is sung, and turns to the active color fragment by fragment
+        let before = screen_at(90);
+        assert!(count(&before, 1) > 0);
+        assert_eq!(count(&before, 2), 0);
+
+        let during = screen_at(145);
+        assert!(count(&during, 1) > 0);
+        assert!(count(&during, 2) > 0);
+        // the second layer has its own colors
+        assert!(count(&during, 3) > 0);
+
+        let after = screen_at(190);
+        assert_eq!(count(&after, 1), 0);
+        assert!(count(&after, 2) > 0);
+
+        // the sung line is cleared after a while
+        assert_eq!(count(&screen_at(500), 2), 0);
+
+        let dir = std::env::temp_dir().join("kfn_cdg_test");
+        let filename = dir.join("song.cdg");
+        kfn.export_cdg(&filename.to_string_lossy()).unwrap();
+        assert_eq!(std::fs::read(dir.join("song.mp3")).unwrap(), vec![0; 10]);
+        assert_eq!(std::fs::read(&filename).unwrap(), cdg);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn subtitles_test() {
+
+        let mut kfn = lyrics_kfn();
+        kfn.data.song.effs[2].texts[0].fragments[0].1 = "<Backing>".to_string();
+        kfn.data.song.effs[2].texts[0].display = "<Backing>".to_string();
+
+        assert_eq!(kfn.to_srt(), "1\n00:00:01,000 --> 00:01:01,500\nHello world\n\n\
+            2\n00:00:02,000 --> 00:00:03,000\n<Backing>\n\n\
+            3\n00:01:01,500 --> 00:01:04,200\nSecond\n\n");
+
+        assert_eq!(kfn.to_vtt(false), "WEBVTT\nTitle: Song\nArtist: Artist\nAlbum: Album\n\n\
+            00:00:01.000 --> 00:01:01.500\nHello world\n\n\
+            00:00:02.000 --> 00:00:03.000\n&lt;Backing&gt;\n\n\
+            00:01:01.500 --> 00:01:04.200\nSecond\n\n");
+
+        assert!(kfn.to_vtt(true).contains("00:00:01.000 --> 00:01:01.500\nHel<00:00:01.200>lo <00:00:01.500>world\n\n"));
+
+        // a line starting together with the next one is still shown
+        kfn.data.song.effs[2].add_line(vec![(200, "Together".to_string())]);
+        let srt = kfn.to_srt();
+        assert!(srt.contains("00:00:02,000 --> 00:00:02,010\n<Backing>\n\n"));
+        assert!(srt.contains("00:00:02,000 --> 00:00:03,000\nTogether\n\n"));
+    }
+
+    #[test]
+    fn frame_test() {
+
+        let png = |color: [u8; 3]| {
+            let mut data: Vec<u8> = Vec::new();
+            image::RgbImage::from_pixel(4, 4, image::Rgb(color))
+                .write_to(&mut std::io::Cursor::new(&mut data), image::ImageFormat::Png).unwrap();
+            data
+        };
+
+        let mut kfn = KfnBuilder::new()
+            .source("song.mp3", vec![0; 10])
+            .background(0, "red.png", png([255, 0, 0]))
+            .background(300, "blue.png", png([0, 0, 255]))
+            .lyrics(LyricLayer::new()
+                .line(vec![(100, "Hel"), (120, "lo "), (150, "world")])
+                .line(vec![(400, "Second")]))
+            .lyrics(LyricLayer::new().line(vec![(200, "Backing")]))
+            .build()
+            .unwrap();
+
+        kfn.data.song.effs[0].anims.push(crate::kfn_ini::eff::Anim {
+            time: 350,
+            anim_entries: vec![AnimEntry { action: Action::ChgColImageColor("#808080FF".to_string()), ..Default::default() }],
+        });
+
+        let count = |frame: &image::RgbaImage, check: &dyn Fn(&image::Rgba<u8>) -> bool| frame.pixels().filter(|pixel| check(pixel)).count();
+        let active = |pixel: &image::Rgba<u8>| pixel[0] > 200 && pixel[1] > 200 && pixel[2] < 60;
+        let inactive = |pixel: &image::Rgba<u8>| pixel[0] > 200 && pixel[1] > 200 && pixel[2] > 200;
+
+        // before the first sync, the first lines are shown in the inactive color
+        let before = kfn.render_frame(500, 160, 120).unwrap();
+        assert_eq!(before.dimensions(), (160, 120));
+        assert_eq!(before.get_pixel(0, 0), &image::Rgba([255, 0, 0, 255]));
+        assert_eq!(count(&before, &active), 0);
+        assert!(count(&before, &inactive) > 0);
+
+        // the sung fragments are in the active color
+        let during = kfn.render_frame(1300, 160, 120).unwrap();
+        assert!(count(&during, &active) > 0);
+        assert!(count(&during, &inactive) > 0);
+
+        // the background changes, then it is tinted
+        assert_eq!(kfn.render_frame(3200, 160, 120).unwrap().get_pixel(0, 0), &image::Rgba([0, 0, 255, 255]));
+        assert_eq!(kfn.render_frame(3600, 160, 120).unwrap().get_pixel(0, 0), &image::Rgba([0, 0, 128, 255]));
+
+        // the same time always gives the same frame
+        let mut renderer = kfn.frame_renderer(160, 120).unwrap();
+        let frames: Vec<image::RgbaImage> = renderer.frames(1000, 1500, 10).collect();
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[3], during);
+
+        let dir = std::env::temp_dir().join("kfn_frame_test");
+        kfn.export_frames(0, 200, 10, 80, 60, &dir.to_string_lossy()).unwrap();
+        let frame = image::open(dir.join("frame_00001.png")).unwrap();
+        assert_eq!((frame.width(), frame.height()), (80, 60));
+        assert!(!dir.join("frame_00002.png").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn video_export_test() {
+
+        let wav = |sample: i16| {
+            let audio = Audio { channels: 1, sample_rate: 8000, samples: vec![sample; 4000] };
+            let mut data: Vec<u8> = Vec::new();
+            audio.write_wav(&mut data).unwrap();
+            data
+        };
+
+        let mut kfn = KfnBuilder::new()
+            .source("song.wav", wav(1000))
+            .vocal_track("vocal.wav", wav(500), false)
+            .lyrics(LyricLayer::new().line(vec![(10, "Hello")]))
+            .build()
+            .unwrap();
+
+        let main = kfn.decode_audio(AudioTrack::Main).unwrap();
+        assert_eq!((main.channels, main.sample_rate, main.duration()), (1, 8000, 500));
+        assert!(main.samples.iter().all(|sample| *sample == 1000));
+
+        // the vocals are mixed into the main track, unless they replace it
+        let vocal = kfn.decode_audio(AudioTrack::Vocal).unwrap();
+        assert!(vocal.samples.iter().all(|sample| *sample == 1500));
+
+        let white = image::RgbaImage::from_pixel(3, 1, image::Rgba([255, 255, 255, 255]));
+        assert_eq!(to_yuv420(&white), vec![235, 235, 235, 128, 128, 128, 128]);
+
+        let dir = std::env::temp_dir().join("kfn_video_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let filename = dir.join("song.y4m");
+        kfn.export_video(&filename.to_string_lossy(), 32, 24, 10, AudioTrack::Main).unwrap();
+
+        let header = "YUV4MPEG2 W32 H24 F10:1 Ip A1:1 C420jpeg\n";
+        let video = std::fs::read(&filename).unwrap();
+        assert!(video.starts_with(header.as_bytes()));
+        assert_eq!(video.len(), header.len() + 5 * (6 + 32 * 24 + 2 * 16 * 12));
+
+        let audio = std::fs::read(dir.join("song.wav")).unwrap();
+        assert_eq!(Audio::decode(audio, None).unwrap(), main);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn preview_test() {
+
+        let mut kfn = lyrics_kfn();
+        let preview = Preview { seconds: 2, width: 64, height: 48, ..Default::default() };
+
+        let gif = kfn.to_preview(&preview).unwrap();
+        let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(gif)).unwrap();
+        let frames = image::AnimationDecoder::into_frames(decoder).collect_frames().unwrap();
+        // the frames only change at the syncs at 1.2, 1.5 and 2 seconds
+        assert_eq!(frames.len(), 4);
+        let durations: Vec<u32> = frames.iter().map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            numer / denom
+        }).collect();
+        assert_eq!(durations, vec![200, 300, 500, 1000]);
+        assert_eq!(frames[0].buffer().dimensions(), (64, 48));
+
+        // sizes a GIF can't hold fail before rendering
+        let zero = Preview { width: 0, ..preview.clone() };
+        assert!(matches!(kfn.to_preview(&zero), Err(KfnParseError::InvalidFrameSize { width: 0, height: 48 })));
+        let wide = Preview { width: 65536, ..preview };
+        assert!(matches!(kfn.to_preview(&wide), Err(KfnParseError::InvalidFrameSize { width: 65536, height: 48 })));
+    }
+
+    #[cfg(feature = "html")]
+    #[test]
+    fn html_test() {
+
+        let mut kfn = lyrics_kfn();
+        kfn.data.song.effs[2].texts[0].fragments[0].1 = "</script>".to_string();
+
+        let html = kfn.to_html().unwrap();
+        assert!(html.contains("<title>Artist - Song</title>"));
+        assert!(html.contains("<audio id=\"audio\" controls src=\"data:audio/mpeg;base64,AAAAAAAAAAAAAA==\"></audio>"));
+        assert!(html.contains("const backgrounds = [[0.00, \"data:image/jpeg;base64,AQ==\"]];"));
+        // the font of the first layer is not embedded, so the default one is used
+        assert!(html.contains("{ font: null, active: \"#FFFF00FF\", inactive: \"#FFFFFFFF\", lines: [\n\
+            [[1.00, \"Hel\"], [1.20, \"lo \"], [1.50, \"world\"]],\n[[61.50, \"Sec\"], [63.20, \"ond\"]]\n] }"));
+        assert!(html.contains("[[2.00, \"\\u003C/script>\"]]"));
+        assert_eq!(html.matches("</script>").count(), 1);
+
+        let dir = std::env::temp_dir().join("kfn_html_test");
+        let filename = dir.join("my song.html");
+        kfn.export_html(&filename.to_string_lossy(), true).unwrap();
+        assert_eq!(std::fs::read(dir.join("my song_files").join("song.mp3")).unwrap(), vec![0; 10]);
+        assert_eq!(std::fs::read(dir.join("my song_files").join("bg.jpg")).unwrap(), vec![1]);
+        let html = std::fs::read_to_string(&filename).unwrap();
+        assert!(html.contains("src=\"my%20song_files/song.mp3\""));
+        assert!(html.contains("[[0.00, \"my%20song_files/bg.jpg\"]]"));
+
+        // an entry name leaving the folder is not extracted
+        let mut kfn = KfnBuilder::new().source("song.mp3", vec![0; 10]).background(0, "../escape.jpg", vec![1]).build().unwrap();
+        kfn.export_html(&filename.to_string_lossy(), true).unwrap();
+        assert!(!dir.join("escape.jpg").exists());
+        assert!(!std::fs::read_to_string(&filename).unwrap().contains("escape.jpg"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pdf_test() {
+
+        let mut kfn = lyrics_kfn();
+        kfn.header.composer = "Composer".to_string();
+        kfn.data.entries.push(Entry {
+            filename: "font.ttf".to_string(),
+            file_type: FileType::Font,
+            file_bin: include_bytes!("fonts/NotoSans-Regular.ttf").to_vec(),
+            ..Default::default()
+        });
+
+        let document = printpdf::lopdf::Document::load_mem(&kfn.to_pdf(true).unwrap()).unwrap();
+        assert_eq!(document.get_pages().len(), 1);
+
+        // the text is written as the glyph IDs of the fonts, a line at a time
+        let page_lines = |document: &printpdf::lopdf::Document| {
+            let page = document.get_pages()[&1];
+            document.get_and_decode_page_content(page).unwrap().operations.iter()
+                .filter(|operation| operation.operator == "Tj")
+                .map(|operation| operation.operands[0].as_str().unwrap().chunks(2).map(|id| u16::from_be_bytes([id[0], id[1]])).collect())
+                .collect::<Vec<Vec<u16>>>()
+        };
+        let glyphs = |font: &'static [u8], text: &str| {
+            let font = rusttype::Font::try_from_bytes(font).unwrap();
+            text.chars().map(|c| font.glyph(c).id().0).collect::<Vec<u16>>()
+        };
+        let default_font = crate::fonts::DefaultFonts::arial();
+        let layer_font: &'static [u8] = include_bytes!("fonts/NotoSans-Regular.ttf");
+
+        let expected = vec![
+            glyphs(default_font, "Song"), glyphs(default_font, "Artist: Artist"), glyphs(default_font, "Album: Album"),
+            glyphs(default_font, "Composer: Composer"),
+            glyphs(default_font, "Layer 1"), glyphs(default_font, "0:01"), glyphs(layer_font, "Hello world"),
+            glyphs(default_font, "1:01"), glyphs(layer_font, "Second"),
+            glyphs(default_font, "Layer 2"), glyphs(default_font, "0:02"), glyphs(default_font, "Backing"),
+        ];
+        assert_eq!(page_lines(&document), expected);
+
+        // the font of the first layer is embedded next to the default one
+        let fonts = document.objects.values()
+            .filter(|object| object.as_dict().is_ok_and(|dict| dict.has(b"FontFile2")))
+            .count();
+        assert_eq!(fonts, 2);
+
+        // long lyrics continue on the next pages, without the timestamps
+        for n in 0..100 {
+            kfn.data.song.effs[2].add_line(vec![(1000 + n, format!("Line {}", n))]);
+        }
+        let document = printpdf::lopdf::Document::load_mem(&kfn.to_pdf(false).unwrap()).unwrap();
+        assert!(document.get_pages().len() > 1);
+        assert!(!page_lines(&document).contains(&glyphs(default_font, "1:01")));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_test() {
+
+        let mut kfn = lyrics_kfn();
+        kfn.data.song.effs[0].anims[0].anim_entries.push(AnimEntry {
+            action: Action::Unknown("ChgSomething:Whatever".to_string(), "1".to_string()),
+            ..Default::default()
+        });
+        kfn.update().unwrap();
+
+        let mut expected: Vec<u8> = Vec::new();
+        kfn.write_to(&mut expected).unwrap();
+
+        let json = serde_json::to_string(&kfn).unwrap();
+        assert!(json.contains("\"file_bin\":\"AAAAAAAAAAAAAA==\""));
+
+        // the document is a valid file again
+        let mut deserialized: Kfn = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.data.song.effs, kfn.data.song.effs);
+
+        let mut output: Vec<u8> = Vec::new();
+        deserialized.write_to(&mut output).unwrap();
+        assert_eq!(output, expected);
+
+        let mut read = Kfn::from_bytes(output);
+        read.parse().unwrap();
+        assert_eq!(read.data.song.effs, kfn.data.song.effs);
+
+        // entries that are not loaded can't be serialized without their payload
+        let mut lazy = Kfn::from_reader_lazy(std::io::Cursor::new(expected.clone())).unwrap();
+        lazy.parse().unwrap();
+        assert!(serde_json::to_string(&lazy).is_err());
+
+        lazy.load_all().unwrap();
+        let json = serde_json::to_string(&lazy).unwrap();
+        let mut deserialized: Kfn = serde_json::from_str(&json).unwrap();
+
+        let mut output: Vec<u8> = Vec::new();
+        deserialized.write_to(&mut output).unwrap();
+        assert_eq!(output, expected);
+    }
+
+    /// Creates an entry from memory for the tests, that don't rely on a file.
+    fn test_entry(filename: &str, file_type: FileType, file_bin: Vec<u8>) -> Entry {
+        Entry {
+            file_type,
+            filename: filename.to_string(),
+            len1: file_bin.len(),
+            offset: 0,
+            len2: file_bin.len(),
+            flags: 0,
+            file_bin,
+            deferred: None,
+        }
     }
 
     #[test]
     fn lyrics_test() {
-        let mut kfn = Kfn::open("test/input.kfn");
+        let mut kfn = Kfn::open("test/input.kfn").unwrap();
         kfn.parse().unwrap();
         dbg!(kfn.get_texts_and_syncs());
     }